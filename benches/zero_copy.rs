@@ -0,0 +1,49 @@
+// Times the two request bodies that zero-copy-threshold chooses between:
+// copying a frame out of its buffer into an owned body, or streaming it from
+// the mapped buffer. Run with `cargo bench --bench zero_copy`.
+extern crate gstreamer as gst;
+
+#[allow(dead_code)]
+#[path = "../src/frame_body.rs"]
+mod frame_body;
+
+use frame_body::FrameBody;
+use futures::{future, TryStreamExt};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+use tokio::runtime::Runtime;
+
+const ITERATIONS: u32 = 20;
+
+// The average time to make a body of the buffer and stream all of it
+fn time(runtime: &mut Runtime, buffer: &gst::Buffer, copy: bool) -> Duration {
+    let started_at = Instant::now();
+    for _ in 0..ITERATIONS {
+        let map = buffer.clone().into_mapped_buffer_readable().unwrap();
+        let body = if copy {
+            FrameBody::from(map.as_slice().to_vec())
+        } else {
+            FrameBody::Mapped(Arc::new(map))
+        };
+        let streamed = runtime
+            .block_on(
+                body.stream(0..body.len())
+                    .try_fold(0, |len, chunk| future::ready(Ok(len + chunk.len()))),
+            )
+            .unwrap();
+        assert_eq!(streamed, buffer.get_size());
+    }
+    started_at.elapsed() / ITERATIONS
+}
+
+fn main() {
+    gst::init().unwrap();
+    let mut runtime = Runtime::new().unwrap();
+    println!("{:>10}  {:>12}  {:>12}", "frame", "copied", "streamed");
+    for &size in &[16 << 10, 256 << 10, 4 << 20, 32 << 20] {
+        let buffer = gst::Buffer::from_slice(vec![1_u8; size]);
+        let copied = time(&mut runtime, &buffer, true);
+        let streamed = time(&mut runtime, &buffer, false);
+        println!("{:>7} KiB  {:>12?}  {:>12?}", size >> 10, copied, streamed);
+    }
+}
@@ -0,0 +1,63 @@
+use futures::future::{AbortHandle, Abortable};
+use std::future::Future;
+use std::sync::{Mutex, MutexGuard};
+use tokio::runtime;
+
+// Returned by block_on when unlock() interrupts the wait
+#[derive(Debug)]
+pub struct Unlocked;
+
+#[derive(Default)]
+struct State {
+    unlocked: bool,
+    abort_handle: Option<AbortHandle>,
+}
+
+// Lets BaseSink's unlock() interrupt the streaming thread while it blocks on
+// the runtime, so flushes and state changes don't wait for slow uploads
+#[derive(Default)]
+pub struct Canceller {
+    state: Mutex<State>,
+}
+
+impl Canceller {
+    // Runs the future to completion, unless unlocked before or while it runs
+    pub fn block_on<F: Future>(
+        &self,
+        runtime: &runtime::Runtime,
+        future: F,
+    ) -> Result<F::Output, Unlocked> {
+        let (abort_handle, registration) = AbortHandle::new_pair();
+        {
+            let mut state = self.lock();
+            if state.unlocked {
+                return Err(Unlocked);
+            }
+            state.abort_handle = Some(abort_handle);
+        }
+        let result = runtime
+            .handle()
+            .block_on(Abortable::new(future, registration));
+        self.lock().abort_handle = None;
+        result.map_err(|_| Unlocked)
+    }
+
+    pub fn unlock(&self) {
+        let mut state = self.lock();
+        state.unlocked = true;
+        if let Some(abort_handle) = state.abort_handle.take() {
+            abort_handle.abort();
+        }
+    }
+
+    pub fn unlock_stop(&self) {
+        self.lock().unlocked = false;
+    }
+
+    // Nothing panics while holding the lock, but a poisoned one is still usable
+    fn lock(&self) -> MutexGuard<'_, State> {
+        self.state
+            .lock()
+            .unwrap_or_else(|poisoned| poisoned.into_inner())
+    }
+}
@@ -0,0 +1,146 @@
+use crate::enum_type;
+use once_cell::sync::Lazy;
+use std::fmt::Write;
+
+// How finely date-partitioned keys are split
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Granularity {
+    Day,
+    Hour,
+    Minute,
+}
+
+// In the order of the enum values
+const GRANULARITIES: [Granularity; 3] = [Granularity::Day, Granularity::Hour, Granularity::Minute];
+
+// The date-partition-granularity property is a GEnum, so gst-inspect lists
+// the granularities
+static GRANULARITY_TYPE: Lazy<glib::Type> = Lazy::new(|| {
+    let names: Vec<&str> = GRANULARITIES
+        .iter()
+        .map(|granularity| granularity.name())
+        .collect();
+    enum_type::register("GstS3MultiFrameSinkDatePartitionGranularity", &names)
+});
+
+pub fn granularity_type() -> glib::Type {
+    *GRANULARITY_TYPE
+}
+
+impl Granularity {
+    pub fn name(self) -> &'static str {
+        match self {
+            Granularity::Day => "day",
+            Granularity::Hour => "hour",
+            Granularity::Minute => "minute",
+        }
+    }
+
+    pub fn to_enum_value(self) -> i32 {
+        GRANULARITIES
+            .iter()
+            .position(|&granularity| granularity == self)
+            .expect("Every granularity is listed") as i32
+    }
+
+    // None for values outside the enum
+    pub fn from_enum_value(value: i32) -> Option<Self> {
+        GRANULARITIES.get(value as usize).copied()
+    }
+}
+
+// The UTC date and time of a Unix timestamp in milliseconds, as
+// (year, month, day, hour, minute)
+pub fn utc_fields(timestamp_ms: u128) -> (i64, u32, u32, u32, u32) {
+    let seconds = (timestamp_ms / 1000) as i64;
+    let days = seconds.div_euclid(86_400);
+    let seconds_of_day = seconds.rem_euclid(86_400);
+    let (year, month, day) = civil_from_days(days);
+    let hour = (seconds_of_day / 3600) as u32;
+    let minute = (seconds_of_day % 3600 / 60) as u32;
+    (year, month, day, hour, minute)
+}
+
+// Hive-style path segments such as `year=2024/month=01/day=15/hour=09`
+pub fn partition(granularity: Granularity, timestamp_ms: u128) -> String {
+    let (year, month, day, hour, minute) = utc_fields(timestamp_ms);
+    let mut path = format!("year={}/month={:02}/day={:02}", year, month, day);
+    if granularity != Granularity::Day {
+        write!(path, "/hour={:02}", hour).unwrap();
+    }
+    if granularity == Granularity::Minute {
+        write!(path, "/minute={:02}", minute).unwrap();
+    }
+    path
+}
+
+// Converts days since 1970-01-01 to a proleptic Gregorian (year, month, day),
+// after Howard Hinnant's civil_from_days
+fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let days = days + 719_468;
+    let era = days.div_euclid(146_097);
+    let day_of_era = days.rem_euclid(146_097);
+    let year_of_era =
+        (day_of_era - day_of_era / 1460 + day_of_era / 36_524 - day_of_era / 146_096) / 365;
+    let day_of_year = day_of_era - (365 * year_of_era + year_of_era / 4 - year_of_era / 100);
+    // Months are counted from March, so that the leap day comes last
+    let shifted_month = (5 * day_of_year + 2) / 153;
+    let day = day_of_year - (153 * shifted_month + 2) / 5 + 1;
+    let month = if shifted_month < 10 {
+        shifted_month + 3
+    } else {
+        shifted_month - 9
+    };
+    let year = year_of_era + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month as u32, day as u32)
+}
+
+pub const WEEKDAYS: [&str; 7] = ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"];
+pub const MONTHS: [&str; 12] = [
+    "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+];
+
+// The day of the week of a Unix timestamp in milliseconds, counted from
+// Monday as 0
+pub fn weekday(timestamp_ms: u128) -> usize {
+    let days = (timestamp_ms / 1000) as i64 / 86_400;
+    // 1970-01-01 was a Thursday
+    (days + 3).rem_euclid(7) as usize
+}
+
+// An RFC 1123 date as used by HTTP headers, e.g. `Thu, 01 Jan 1970 00:00:00 GMT`
+pub fn http_date(timestamp_ms: u128) -> String {
+    let seconds = (timestamp_ms / 1000) as i64;
+    let (year, month, day, hour, minute) = utc_fields(timestamp_ms);
+    format!(
+        "{}, {:02} {} {} {:02}:{:02}:{:02} GMT",
+        WEEKDAYS[weekday(timestamp_ms)],
+        day,
+        MONTHS[month as usize - 1],
+        year,
+        hour,
+        minute,
+        seconds.rem_euclid(60)
+    )
+}
+
+// Checks the shape of an RFC 1123 date, without checking that the weekday
+// matches the date
+pub fn is_http_date(date: &str) -> bool {
+    let digits = |field: &str, len: usize| {
+        field.len() == len && field.bytes().all(|byte| byte.is_ascii_digit())
+    };
+    let fields: Vec<&str> = date.split(' ').collect();
+    match fields.as_slice() {
+        [weekday, day, month, year, time, "GMT"] => {
+            let time: Vec<&str> = time.split(':').collect();
+            matches!(weekday.strip_suffix(','), Some(weekday) if WEEKDAYS.contains(&weekday))
+                && digits(day, 2)
+                && MONTHS.contains(month)
+                && digits(year, 4)
+                && time.len() == 3
+                && time.iter().all(|field| digits(field, 2))
+        }
+        _ => false,
+    }
+}
@@ -0,0 +1,35 @@
+use glib::translate::from_glib;
+use std::ffi::CString;
+use std::ptr;
+
+// Registers a GEnum whose values are numbered in the order of the names, so
+// that tools such as gst-inspect can list the valid values of a property.
+// Each value is both named and nicked by its name.
+pub fn register(type_name: &str, names: &[&str]) -> glib::Type {
+    let mut values: Vec<gobject_sys::GEnumValue> = names
+        .iter()
+        .enumerate()
+        .map(|(index, &name)| {
+            // GLib holds on to the names for the life of the process
+            let name = CString::new(name).unwrap().into_raw();
+            gobject_sys::GEnumValue {
+                value: index as i32,
+                value_name: name,
+                value_nick: name,
+            }
+        })
+        .collect();
+    values.push(gobject_sys::GEnumValue {
+        value: 0,
+        value_name: ptr::null(),
+        value_nick: ptr::null(),
+    });
+    let values = Box::leak(values.into_boxed_slice());
+    let type_name = CString::new(type_name).unwrap();
+    unsafe {
+        from_glib(gobject_sys::g_enum_register_static(
+            type_name.as_ptr(),
+            values.as_ptr(),
+        ))
+    }
+}
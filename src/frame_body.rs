@@ -0,0 +1,56 @@
+use bytes::Bytes;
+use futures::{future, stream};
+use rusoto_core::ByteStream;
+use std::ops::{Deref, Range};
+use std::sync::Arc;
+
+// How much of a mapped buffer is copied at a time while streaming it
+const STREAM_CHUNK_SIZE: usize = 64 * 1024;
+
+// The bytes of an upload. Frames uploaded as rendered keep the mapped buffer
+// rather than copying it into a Vec; anything transformed is owned.
+#[derive(Clone)]
+pub enum FrameBody {
+    Owned(Bytes),
+    Mapped(Arc<gst::MappedBuffer<gst::buffer::Readable>>),
+}
+
+impl FrameBody {
+    // Makes a request body of the range without copying owned bytes. Mapped
+    // buffers are copied a chunk at a time as the request is written.
+    pub fn stream(&self, range: Range<usize>) -> ByteStream {
+        let len = range.len();
+        match *self {
+            FrameBody::Owned(ref bytes) => {
+                let bytes = bytes.slice(range);
+                ByteStream::new_with_size(stream::once(future::ready(Ok(bytes))), len)
+            }
+            FrameBody::Mapped(ref buffer) => {
+                let buffer = buffer.clone();
+                let end = range.end;
+                let chunks = range.step_by(STREAM_CHUNK_SIZE).map(move |start| {
+                    let chunk = &buffer.as_slice()[start..end.min(start + STREAM_CHUNK_SIZE)];
+                    Ok(Bytes::copy_from_slice(chunk))
+                });
+                ByteStream::new_with_size(stream::iter(chunks), len)
+            }
+        }
+    }
+}
+
+impl Deref for FrameBody {
+    type Target = [u8];
+
+    fn deref(&self) -> &[u8] {
+        match *self {
+            FrameBody::Owned(ref bytes) => bytes,
+            FrameBody::Mapped(ref buffer) => buffer.as_slice(),
+        }
+    }
+}
+
+impl From<Vec<u8>> for FrameBody {
+    fn from(vec: Vec<u8>) -> Self {
+        FrameBody::Owned(vec.into())
+    }
+}
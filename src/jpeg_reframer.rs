@@ -0,0 +1,213 @@
+const SOI: [u8; 2] = [0xFF, 0xD8];
+
+// Splits a JPEG elementary stream whose buffers don't line up with image
+// boundaries into complete images, each running from its SOI to its EOI marker.
+//
+// The segments between the markers are walked by their lengths rather than by
+// searching for EOI, so that thumbnails embedded in EXIF data, which have
+// markers of their own, don't end the image early.
+#[derive(Debug, Default)]
+pub struct JpegReframer {
+    pending: Vec<u8>,
+    // How far into the pending image the walk has got, so each push only
+    // walks the bytes it added
+    scan: Scan,
+}
+
+// A position in the pending image, and what is expected there
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Scan {
+    // The marker of the next segment
+    Segment(usize),
+    // Entropy-coded data following a start of scan header
+    EntropyCoded(usize),
+    // Anything, falling back to the first EOI after a malformed marker
+    Eoi(usize),
+}
+
+impl Default for Scan {
+    fn default() -> Self {
+        Scan::Segment(SOI.len())
+    }
+}
+
+impl JpegReframer {
+    // Adds the bytes to the stream, returning every image they complete
+    pub fn push(&mut self, data: &[u8]) -> Vec<Vec<u8>> {
+        self.pending.extend_from_slice(data);
+        let mut images = Vec::new();
+        loop {
+            // Bytes outside an image are of no use to anyone
+            match self.pending.windows(2).position(|window| window == SOI) {
+                Some(start) => drop(self.pending.drain(..start)),
+                None => {
+                    // Keep a trailing 0xFF in case it is the start of the next SOI
+                    let keep = usize::from(self.pending.last() == Some(&0xFF));
+                    let len = self.pending.len();
+                    self.pending.drain(..len - keep);
+                    break;
+                }
+            }
+            match image_len(&self.pending, &mut self.scan) {
+                Some(len) => {
+                    images.push(self.pending.drain(..len).collect());
+                    self.scan = Scan::default();
+                }
+                None => break,
+            }
+        }
+        images
+    }
+
+    // The number of bytes of the incomplete image held back
+    pub fn pending(&self) -> usize {
+        self.pending.len()
+    }
+
+    // Discards the incomplete image, e.g. after part of the stream was lost
+    pub fn reset(&mut self) {
+        self.pending.clear();
+        self.scan = Scan::default();
+    }
+}
+
+// The length of the complete image at the start of the data, which begins with
+// an SOI marker, or None if its EOI hasn't arrived yet. Picks up from the scan,
+// and leaves it where the walk stopped.
+fn image_len(data: &[u8], scan: &mut Scan) -> Option<usize> {
+    loop {
+        match *scan {
+            Scan::Segment(pos) => {
+                if pos + 2 > data.len() {
+                    return None;
+                }
+                if data[pos] != 0xFF {
+                    // Not a well-formed marker, so fall back to the first EOI
+                    *scan = Scan::Eoi(pos);
+                    continue;
+                }
+                *scan = match data[pos + 1] {
+                    // Fill bytes may pad the space between markers
+                    0xFF => Scan::Segment(pos + 1),
+                    0xD9 => return Some(pos + 2),
+                    // Markers without a length
+                    0x01 | 0xD0..=0xD7 => Scan::Segment(pos + 2),
+                    marker => {
+                        let length = usize::from(u16::from_be_bytes([
+                            *data.get(pos + 2)?,
+                            *data.get(pos + 3)?,
+                        ]));
+                        // The start of scan header is followed by entropy-coded data
+                        if marker == 0xDA {
+                            Scan::EntropyCoded(pos + 2 + length)
+                        } else {
+                            Scan::Segment(pos + 2 + length)
+                        }
+                    }
+                };
+            }
+            // Runs until the next marker other than a restart marker. Data
+            // bytes of 0xFF are escaped as 0xFF 0x00.
+            Scan::EntropyCoded(mut pos) => loop {
+                let (byte, next) = match (data.get(pos), data.get(pos + 1)) {
+                    (Some(&byte), Some(&next)) => (byte, next),
+                    _ => {
+                        *scan = Scan::EntropyCoded(pos);
+                        return None;
+                    }
+                };
+                if byte == 0xFF && next != 0x00 && !(0xD0..=0xD7).contains(&next) {
+                    *scan = Scan::Segment(pos);
+                    break;
+                }
+                pos += 1;
+            },
+            Scan::Eoi(from) => {
+                let len = find_eoi(data, from);
+                // The last byte may be the first half of the EOI
+                *scan = Scan::Eoi(from.max(data.len().saturating_sub(1)));
+                return len;
+            }
+        }
+    }
+}
+
+fn find_eoi(data: &[u8], from: usize) -> Option<usize> {
+    data[from..]
+        .windows(2)
+        .position(|window| window == [0xFF, 0xD9])
+        .map(|offset| from + offset + 2)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // SOI, an APP0 segment, a start of scan with an escaped 0xFF and a restart
+    // marker in its data, then EOI
+    fn image(fill: u8) -> Vec<u8> {
+        let mut image = SOI.to_vec();
+        image.extend_from_slice(&[0xFF, 0xE0, 0x00, 0x04, fill, fill]);
+        image.extend_from_slice(&[0xFF, 0xDA, 0x00, 0x02]);
+        image.extend_from_slice(&[fill, 0xFF, 0x00, fill, 0xFF, 0xD3, fill]);
+        image.extend_from_slice(&[0xFF, 0xD9]);
+        image
+    }
+
+    #[test]
+    fn reassembles_an_image_split_at_every_byte() {
+        let image = image(0x11);
+        for split in 1..image.len() {
+            let mut reframer = JpegReframer::default();
+            assert!(reframer.push(&image[..split]).is_empty());
+            assert_eq!(reframer.push(&image[split..]), vec![image.clone()]);
+            assert_eq!(reframer.pending(), 0);
+        }
+    }
+
+    #[test]
+    fn reassembles_an_image_pushed_a_byte_at_a_time() {
+        let image = image(0x22);
+        let mut reframer = JpegReframer::default();
+        let mut images = Vec::new();
+        for byte in &image {
+            images.extend(reframer.push(std::slice::from_ref(byte)));
+        }
+        assert_eq!(images, vec![image]);
+    }
+
+    #[test]
+    fn finds_markers_split_across_buffers() {
+        let first = image(0x33);
+        let second = image(0x44);
+        let mut reframer = JpegReframer::default();
+        // The SOI of the first image and the EOI of the second straddle pushes
+        assert!(reframer.push(&first[..1]).is_empty());
+        let mut stream = first[1..].to_vec();
+        stream.extend_from_slice(&second[..second.len() - 1]);
+        assert_eq!(reframer.push(&stream), vec![first]);
+        assert_eq!(reframer.push(&second[second.len() - 1..]), vec![second]);
+    }
+
+    #[test]
+    fn drops_garbage_before_the_soi() {
+        let image = image(0x55);
+        let mut reframer = JpegReframer::default();
+        assert!(reframer.push(&[0x00, 0x12, 0xD8, 0xFF]).is_empty());
+        // Only the trailing 0xFF is held back, in case it starts an SOI
+        assert_eq!(reframer.pending(), 1);
+        let mut stream = vec![0xD9, 0x42];
+        stream.extend_from_slice(&image);
+        assert_eq!(reframer.push(&stream), vec![image]);
+        assert_eq!(reframer.pending(), 0);
+    }
+
+    #[test]
+    fn falls_back_to_the_first_eoi_after_a_malformed_marker() {
+        let mut image = SOI.to_vec();
+        image.extend_from_slice(&[0x12, 0x34, 0xFF]);
+        let mut reframer = JpegReframer::default();
+        assert!(reframer.push(&image).is_empty());
+        assert_eq!(reframer.push(&[0xD9]), vec![[&image[..], &[0xD9]].concat()]);
+    }
+}
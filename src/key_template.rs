@@ -0,0 +1,121 @@
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Segment {
+    Literal(String),
+    Prefix,
+    Frame(usize),
+    Pts,
+    Timestamp,
+    Meta,
+    KeySuffix,
+}
+
+// The per-frame values substituted for template tokens
+#[derive(Debug, Clone)]
+pub struct KeyFields<'a> {
+    pub prefix: &'a str,
+    pub frame_num: u64,
+    pub pts: Option<u64>,
+    // Already formatted, see timestamp-format
+    pub timestamp: &'a str,
+    pub meta: Option<u64>,
+    pub key_suffix: &'a str,
+}
+
+// A parsed object key template such as `{prefix}/frame{frame:06}`.
+// The key suffix, usually a dot and the file extension, is appended when the
+// template is rendered.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KeyTemplate {
+    segments: Vec<Segment>,
+}
+
+impl KeyTemplate {
+    pub fn render(&self, fields: &KeyFields, suffix: &str) -> String {
+        let mut key = self.expand(fields);
+        key.push_str(suffix);
+        key
+    }
+
+    // Substitutes the tokens without appending a suffix, for templated
+    // values other than the object key
+    pub fn expand(&self, fields: &KeyFields) -> String {
+        let mut key = String::new();
+        for segment in &self.segments {
+            match *segment {
+                Segment::Literal(ref literal) => key.push_str(literal),
+                Segment::Prefix => key.push_str(fields.prefix),
+                Segment::Frame(width) => {
+                    write!(key, "{:0width$}", fields.frame_num, width = width).unwrap()
+                }
+                Segment::Pts => push_optional(&mut key, fields.pts),
+                Segment::Timestamp => key.push_str(fields.timestamp),
+                Segment::Meta => push_optional(&mut key, fields.meta),
+                Segment::KeySuffix => key.push_str(fields.key_suffix),
+            }
+        }
+        key
+    }
+}
+
+impl FromStr for KeyTemplate {
+    type Err = String;
+
+    fn from_str(template: &str) -> Result<Self, Self::Err> {
+        let mut segments = Vec::new();
+        let mut literal = String::new();
+        let mut chars = template.chars();
+        while let Some(c) = chars.next() {
+            match c {
+                '{' => {
+                    let mut token = String::new();
+                    loop {
+                        match chars.next() {
+                            Some('}') => break,
+                            Some(c) => token.push(c),
+                            None => return Err(format!("unclosed {{ in {}", template)),
+                        }
+                    }
+                    if !literal.is_empty() {
+                        segments.push(Segment::Literal(literal.split_off(0)));
+                    }
+                    segments.push(parse_token(&token)?);
+                }
+                '}' => return Err(format!("unmatched }} in {}", template)),
+                c => literal.push(c),
+            }
+        }
+        if !literal.is_empty() {
+            segments.push(Segment::Literal(literal));
+        }
+        Ok(KeyTemplate { segments })
+    }
+}
+
+fn push_optional(key: &mut String, value: Option<u64>) {
+    match value {
+        Some(value) => write!(key, "{}", value).unwrap(),
+        None => key.push_str("none"),
+    }
+}
+
+fn parse_token(token: &str) -> Result<Segment, String> {
+    let mut parts = token.splitn(2, ':');
+    let name = parts.next().unwrap_or_default();
+    let spec = parts.next();
+    match (name, spec) {
+        ("prefix", None) => Ok(Segment::Prefix),
+        ("frame", None) => Ok(Segment::Frame(0)),
+        ("frame", Some(width)) => width
+            .parse()
+            .map(Segment::Frame)
+            .map_err(|_| format!("invalid frame width {} in {{{}}}", width, token)),
+        ("pts", None) => Ok(Segment::Pts),
+        ("timestamp", None) => Ok(Segment::Timestamp),
+        ("meta", None) => Ok(Segment::Meta),
+        ("suffix", None) => Ok(Segment::KeySuffix),
+        _ => Err(format!("unknown token {{{}}}", token)),
+    }
+}
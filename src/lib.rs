@@ -6,8 +6,20 @@ extern crate gstreamer_base as gst_base;
 extern crate gstreamer_video as gst_video;
 extern crate once_cell;
 
-mod put_object_handler;
+mod canceller;
+mod date_partition;
+mod enum_type;
+mod frame_body;
+mod jpeg_reframer;
+mod key_template;
+mod rate_limiter;
+mod region;
+mod request_dispatcher;
+mod retry_handler;
 mod s3multiframesink;
+mod tar_archive;
+mod timestamp_format;
+mod upload_queue;
 fn plugin_init(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
     s3multiframesink::register(plugin)?;
     Ok(())
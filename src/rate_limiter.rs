@@ -0,0 +1,33 @@
+use std::time::{Duration, Instant};
+
+// A token bucket allowing bursts of up to one second's worth of uploads
+pub struct RateLimiter {
+    per_second: f64,
+    tokens: f64,
+    last_refill: Instant,
+}
+
+impl RateLimiter {
+    pub fn new(per_second: u32) -> Self {
+        RateLimiter {
+            per_second: f64::from(per_second),
+            tokens: f64::from(per_second),
+            last_refill: Instant::now(),
+        }
+    }
+
+    // Takes a token, returning how long to wait before it may be spent
+    pub fn acquire(&mut self) -> Duration {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.last_refill).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.per_second).min(self.per_second);
+        self.last_refill = now;
+        self.tokens -= 1.0;
+        if self.tokens >= 0.0 {
+            Duration::from_secs(0)
+        } else {
+            // The debt is paid back by the refill during the wait
+            Duration::from_secs_f64(-self.tokens / self.per_second)
+        }
+    }
+}
@@ -0,0 +1,136 @@
+use crate::enum_type;
+use once_cell::sync::Lazy;
+use rusoto_core::Region;
+
+// The nick of the enum value for S3-compatible services, which is signed as
+// us-east-1 and needs the endpoint property
+pub const CUSTOM: &str = "custom";
+
+// Every region rusoto knows, in the order of the enum values. Custom follows.
+const REGIONS: [Region; 25] = [
+    Region::ApEast1,
+    Region::ApNortheast1,
+    Region::ApNortheast2,
+    Region::ApNortheast3,
+    Region::ApSouth1,
+    Region::ApSoutheast1,
+    Region::ApSoutheast2,
+    Region::CaCentral1,
+    Region::EuCentral1,
+    Region::EuWest1,
+    Region::EuWest2,
+    Region::EuWest3,
+    Region::EuNorth1,
+    Region::EuSouth1,
+    Region::MeSouth1,
+    Region::SaEast1,
+    Region::UsEast1,
+    Region::UsEast2,
+    Region::UsWest1,
+    Region::UsWest2,
+    Region::UsGovEast1,
+    Region::UsGovWest1,
+    Region::CnNorth1,
+    Region::CnNorthwest1,
+    Region::AfSouth1,
+];
+
+// The region property is a GEnum, so tools such as gst-inspect can list the
+// valid regions. Values are named after the region, e.g. eu-west-2.
+static REGION_TYPE: Lazy<glib::Type> = Lazy::new(|| {
+    let names: Vec<&str> = REGIONS
+        .iter()
+        .map(|region| region.name())
+        .chain(std::iter::once(CUSTOM))
+        .collect();
+    enum_type::register("GstS3MultiFrameSinkRegion", &names)
+});
+
+pub fn region_type() -> glib::Type {
+    *REGION_TYPE
+}
+
+pub fn custom() -> Region {
+    Region::Custom {
+        name: Region::UsEast1.name().to_string(),
+        endpoint: String::new(),
+    }
+}
+
+pub fn to_enum_value(region: &Region) -> i32 {
+    REGIONS
+        .iter()
+        .position(|known| known == region)
+        .unwrap_or(REGIONS.len()) as i32
+}
+
+pub fn from_enum_value(value: i32) -> Region {
+    REGIONS.get(value as usize).cloned().unwrap_or_else(custom)
+}
+
+// Normalizes the endpoint property. Its scheme decides whether requests use
+// TLS, so an endpoint without one is given https, which is what rusoto would
+// assume, and any scheme other than http or https is rejected. A trailing
+// slash is dropped so that paths don't start with `//`.
+pub fn parse_endpoint(endpoint: &str) -> Result<String, String> {
+    let endpoint = endpoint.trim();
+    let (scheme, host) = match endpoint.split_once("://") {
+        Some((scheme, host)) => (scheme.to_ascii_lowercase(), host.trim_end_matches('/')),
+        None => ("https".to_string(), endpoint.trim_end_matches('/')),
+    };
+    if scheme != "http" && scheme != "https" {
+        return Err(format!("unsupported scheme {} in {}", scheme, endpoint));
+    }
+    if host.is_empty() {
+        return Err(format!("no host in {}", endpoint));
+    }
+    Ok(format!("{}://{}", scheme, host))
+}
+
+// The location constraint to create a bucket in the region with. S3 rejects
+// an explicit us-east-1, since buckets created without one already live there.
+pub fn location_constraint(region: &Region) -> Option<String> {
+    match region.name() {
+        "us-east-1" => None,
+        name => Some(name.to_string()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parse_endpoint_keeps_the_scheme() {
+        assert_eq!(
+            parse_endpoint("http://localhost:9000").unwrap(),
+            "http://localhost:9000"
+        );
+        assert_eq!(
+            parse_endpoint("https://minio.example.com").unwrap(),
+            "https://minio.example.com"
+        );
+        assert_eq!(
+            parse_endpoint("HTTP://localhost:9000/").unwrap(),
+            "http://localhost:9000"
+        );
+        assert_eq!(
+            parse_endpoint("HTTPS://minio.example.com").unwrap(),
+            "https://minio.example.com"
+        );
+    }
+
+    #[test]
+    fn parse_endpoint_defaults_to_https() {
+        assert_eq!(
+            parse_endpoint("localhost:9000").unwrap(),
+            "https://localhost:9000"
+        );
+    }
+
+    #[test]
+    fn parse_endpoint_rejects_other_schemes() {
+        assert!(parse_endpoint("ftp://localhost").is_err());
+        assert!(parse_endpoint("http://").is_err());
+    }
+}
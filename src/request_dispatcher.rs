@@ -0,0 +1,96 @@
+use rusoto_core::request::DispatchSignedRequestFuture;
+use rusoto_core::signature::SignedRequest;
+use rusoto_core::{DispatchSignedRequest, HttpClient};
+use std::time::Duration;
+
+// Adjusts requests the generated S3 client has no options for before passing
+// them on to the HTTP client.
+//
+// It applies a default timeout to every request, since the client never passes
+// one to its dispatcher. A timed out request fails with an HttpDispatch error,
+// which the upload retry handler treats as transient.
+//
+// With no_overwrite it adds `If-None-Match: *` to requests that create objects,
+// so that S3 answers 412 Precondition Failed instead of replacing an existing
+// key. The header is added after signing, which S3 allows for headers outside
+// the x-amz- namespace.
+//
+// It also adds any custom headers to every request, for gateways and proxies
+// that want e.g. an API key. These are unsigned in the same way.
+pub struct RequestDispatcher {
+    inner: HttpClient,
+    timeout: Option<Duration>,
+    no_overwrite: bool,
+    headers: Vec<(String, String)>,
+}
+
+impl RequestDispatcher {
+    pub fn new(
+        inner: HttpClient,
+        timeout: Option<Duration>,
+        no_overwrite: bool,
+        headers: Vec<(String, String)>,
+    ) -> Self {
+        RequestDispatcher {
+            inner,
+            timeout,
+            no_overwrite,
+            headers,
+        }
+    }
+}
+
+// Parses `Name:Value;Name2:Value2`. The headers that requests are signed with
+// can't be set, since changing them after signing breaks the signature.
+pub fn parse_headers(headers: &str) -> Result<Vec<(String, String)>, String> {
+    headers
+        .split(';')
+        .filter(|header| !header.trim().is_empty())
+        .map(|header| {
+            let (name, value) = header
+                .split_once(':')
+                .ok_or_else(|| format!("header {} has no value", header))?;
+            let name = name.trim();
+            let lower = name.to_ascii_lowercase();
+            if name.is_empty() || name.contains(char::is_whitespace) {
+                Err(format!("invalid header name {:?}", name))
+            } else if lower == "host" || lower == "authorization" || lower.starts_with("x-amz-") {
+                Err(format!("header {} is used for signing", name))
+            } else {
+                Ok((name.to_string(), value.trim().to_string()))
+            }
+        })
+        .collect()
+}
+
+// PutObject and CompleteMultipartUpload are the requests that write an object.
+// Requests are path-style, so object paths are /bucket/key.
+fn creates_object(request: &SignedRequest) -> bool {
+    let has_key = match request.path().trim_start_matches('/').split_once('/') {
+        Some((_, key)) => !key.is_empty(),
+        None => false,
+    };
+    let is_part = request.params.contains_key("partNumber");
+    let is_completion = request.params.contains_key("uploadId");
+    match request.method() {
+        "PUT" => has_key && !is_part && !request.headers().contains_key("x-amz-copy-source"),
+        "POST" => has_key && is_completion,
+        _ => false,
+    }
+}
+
+impl DispatchSignedRequest for RequestDispatcher {
+    fn dispatch(
+        &self,
+        mut request: SignedRequest,
+        timeout: Option<Duration>,
+    ) -> DispatchSignedRequestFuture {
+        if self.no_overwrite && creates_object(&request) {
+            request.add_header("If-None-Match", "*");
+        }
+        for (name, value) in &self.headers {
+            request.add_header(name, value);
+        }
+        self.inner.dispatch(request, timeout.or(self.timeout))
+    }
+}
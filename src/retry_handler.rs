@@ -0,0 +1,289 @@
+use futures_retry::ErrorHandler;
+use futures_retry::RetryPolicy;
+use gst::prelude::*;
+use rand::prelude::StdRng;
+use rand::Rng;
+use rusoto_core::RusotoError;
+use rusoto_s3::PutObjectError;
+use std::convert::TryInto;
+use std::error::Error;
+use std::marker::PhantomData;
+use std::ops::Div;
+use std::time::Duration;
+
+// The bounds of the jittered exponential backoff between attempts
+#[derive(Debug, Clone, Copy)]
+pub struct Backoff {
+    pub base: Duration,
+    // Used instead of base for throttled requests, which need to back off
+    // further to ease the load
+    pub throttle_base: Duration,
+    pub max: Duration,
+}
+
+// Retries failed S3 requests of any operation with jittered exponential backoff
+pub struct S3RetryHandler<E> {
+    max_attempts: usize,
+    // Posts a retry message on the bus for each failed attempt, and is the
+    // object of the log messages
+    element: gst::Element,
+    cat: gst::DebugCategory,
+    // What is being retried, e.g. `frame 12`, for log messages
+    operation: String,
+    // The frame the request uploads, if any
+    frame_num: Option<u64>,
+    backoff: Backoff,
+    rng: StdRng,
+    error: PhantomData<fn() -> E>,
+}
+
+pub type PutObjectHandler = S3RetryHandler<PutObjectError>;
+
+impl<E> S3RetryHandler<E> {
+    pub fn new(
+        max_attempts: usize,
+        element: gst::Element,
+        cat: gst::DebugCategory,
+        operation: String,
+        frame_num: Option<u64>,
+        backoff: Backoff,
+    ) -> Self {
+        S3RetryHandler {
+            max_attempts,
+            element,
+            cat,
+            operation,
+            frame_num,
+            backoff,
+            rng: rand::SeedableRng::from_entropy(),
+            error: PhantomData,
+        }
+    }
+    pub fn jitter(&mut self, attempt: usize, base: Duration) -> Duration {
+        // saturate at the maximum rather than overflowing for large attempt counts
+        let backoff = attempt
+            .try_into()
+            .ok()
+            .and_then(|exponent| 2_u32.checked_pow(exponent))
+            .and_then(|factor| base.checked_mul(factor))
+            .unwrap_or(self.backoff.max);
+        let temp = self.backoff.max.min(backoff);
+        let spread = temp.div(2).as_millis();
+        if spread == 0 {
+            // gen_range panics on an empty range, which a zero base would produce
+            return temp / 2;
+        }
+        temp / 2
+            + Duration::from_millis(self.rng.gen_range(0, spread).try_into().unwrap_or(u64::MAX))
+    }
+
+    // Lets monitoring code follow retries without scraping the logs
+    fn post_retry_message(&self, attempt: usize, error: &RusotoError<E>, delay: Option<Duration>)
+    where
+        E: Error + 'static,
+    {
+        let mut structure = gst::Structure::builder("s3multiframesink-retry")
+            .field("operation", &self.operation)
+            .field("attempt", &(attempt as u32))
+            .field("max-attempts", &(self.max_attempts as u32))
+            .field("error-kind", &error_kind(error))
+            .field("error", &error.to_string())
+            .field("retrying", &delay.is_some())
+            .build();
+        if let Some(frame_num) = self.frame_num {
+            structure.set("frame", &frame_num);
+        }
+        if let Some(delay) = delay {
+            structure.set("delay-ms", &(delay.as_millis() as u64));
+        }
+        let message = gst::Message::new_element(structure)
+            .src(Some(&self.element))
+            .build();
+        // Fails only without a bus, when nobody could be listening
+        let _ = self.element.post_message(&message);
+    }
+}
+
+impl<E: Error + 'static> ErrorHandler<RusotoError<E>> for S3RetryHandler<E> {
+    type OutError = RusotoError<E>;
+
+    fn handle(&mut self, attempt: usize, error: RusotoError<E>) -> RetryPolicy<Self::OutError> {
+        if !is_transient(&error) {
+            // Only a warning, as callers expect some of these, e.g. a 404 for a
+            // missing bucket
+            gst_warning!(
+                self.cat,
+                obj: &self.element,
+                "Not retrying {} after a permanent error. Error: {}",
+                self.operation,
+                error
+            );
+            self.post_retry_message(attempt, &error, None);
+            return RetryPolicy::ForwardError(error);
+        }
+        // `attempt` starts at 1, so a `max_attempts` of 0 forwards the first failure
+        if attempt > self.max_attempts {
+            gst_error!(
+                self.cat,
+                obj: &self.element,
+                "Attempts exhausted for {}. Error: {}",
+                self.operation,
+                error
+            );
+            self.post_retry_message(attempt, &error, None);
+            RetryPolicy::ForwardError(error)
+        } else {
+            gst_warning!(
+                self.cat,
+                obj: &self.element,
+                "Attempt {}/{} of {} has failed. Error: {}",
+                attempt,
+                self.max_attempts,
+                self.operation,
+                error
+            );
+            // A delay suggested by a throttled response wins over our own backoff,
+            // capped like it so a bogus hint can't stall the pipeline. Other
+            // errors always back off as usual.
+            let delay = match retry_after(&error) {
+                Some(delay) => delay.min(self.backoff.max),
+                None if error_kind(&error) == "throttled" => {
+                    self.jitter(attempt, self.backoff.throttle_base)
+                }
+                None => self.jitter(attempt, self.backoff.base),
+            };
+            self.post_retry_message(attempt, &error, Some(delay));
+            RetryPolicy::WaitRetry(delay)
+        }
+    }
+}
+
+// The Retry-After header of a throttled response, when given in seconds. The
+// HTTP date form isn't used by S3 and is ignored.
+fn retry_after<E>(error: &RusotoError<E>) -> Option<Duration> {
+    if error_kind(error) != "throttled" {
+        return None;
+    }
+    match error {
+        RusotoError::Unknown(response) => response
+            .headers
+            .get("retry-after")
+            .and_then(|value| value.trim().parse().ok())
+            .map(Duration::from_secs),
+        _ => None,
+    }
+}
+
+// A coarse classification of a failed request, for retry messages
+pub fn error_kind<E>(error: &RusotoError<E>) -> &'static str {
+    match error {
+        RusotoError::HttpDispatch(_) => "dispatch",
+        RusotoError::Credentials(_) => "credentials",
+        RusotoError::Service(_) => "service",
+        RusotoError::Validation(_) => "validation",
+        RusotoError::ParseError(_) => "parse",
+        RusotoError::Blocking => "blocking",
+        RusotoError::Unknown(response) => {
+            let status = response.status.as_u16();
+            // S3 reports some timeouts and throttling as 400s, told apart by their code
+            let body = String::from_utf8_lossy(&response.body);
+            let has_code = |code: &str| body.contains(&format!("<Code>{}</Code>", code));
+            if status == 429 || has_code("SlowDown") || has_code("Throttling") {
+                "throttled"
+            } else if status == 408 || has_code("RequestTimeout") {
+                "timeout"
+            } else if status >= 500 {
+                "server"
+            } else {
+                "client"
+            }
+        }
+    }
+}
+
+// Network failures, throttling and server-side errors may succeed on a later
+// attempt. Credential, validation and other client errors never will. S3
+// errors such as a 500 or 503 usually arrive untyped, as RusotoError::Unknown,
+// so they are classified by status code.
+pub fn is_transient<E>(error: &RusotoError<E>) -> bool {
+    matches!(
+        error_kind(error),
+        "dispatch" | "throttled" | "timeout" | "server"
+    )
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use rusoto_core::credential::CredentialsError;
+    use rusoto_core::request::BufferedHttpResponse;
+    use rusoto_core::HttpDispatchError;
+
+    fn unknown(status: u16, code: Option<&str>) -> RusotoError<PutObjectError> {
+        let body = match code {
+            Some(code) => format!("<Error><Code>{}</Code></Error>", code),
+            None => String::new(),
+        };
+        RusotoError::Unknown(BufferedHttpResponse {
+            status: http::StatusCode::from_u16(status).unwrap(),
+            body: body.into(),
+            headers: Default::default(),
+        })
+    }
+
+    fn with_retry_after(
+        mut error: RusotoError<PutObjectError>,
+        seconds: &str,
+    ) -> RusotoError<PutObjectError> {
+        if let RusotoError::Unknown(ref mut response) = error {
+            response.headers.insert("retry-after", seconds.to_string());
+        }
+        error
+    }
+
+    #[test]
+    fn retry_after_is_only_honoured_when_throttled() {
+        let slow_down = with_retry_after(unknown(503, Some("SlowDown")), "2");
+        assert_eq!(retry_after(&slow_down), Some(Duration::from_secs(2)));
+        let too_many = with_retry_after(unknown(429, None), "1");
+        assert_eq!(retry_after(&too_many), Some(Duration::from_secs(1)));
+        assert_eq!(retry_after(&unknown(429, None)), None);
+        let unavailable = with_retry_after(unknown(503, None), "30");
+        assert_eq!(retry_after(&unavailable), None);
+        let internal = with_retry_after(unknown(500, Some("InternalError")), "30");
+        assert_eq!(retry_after(&internal), None);
+    }
+
+    #[test]
+    fn retries_network_timeout_and_throttling_errors() {
+        let dispatch = RusotoError::HttpDispatch(HttpDispatchError::new("reset".to_string()));
+        assert!(is_transient::<PutObjectError>(&dispatch));
+        assert_eq!(error_kind(&unknown(408, None)), "timeout");
+        assert_eq!(error_kind(&unknown(400, Some("RequestTimeout"))), "timeout");
+        assert_eq!(error_kind(&unknown(400, Some("Throttling"))), "throttled");
+        assert_eq!(error_kind(&unknown(503, Some("SlowDown"))), "throttled");
+        assert!(is_transient(&unknown(400, Some("RequestTimeout"))));
+        assert!(is_transient(&unknown(400, Some("Throttling"))));
+    }
+
+    #[test]
+    fn forwards_permanent_errors() {
+        let credentials = RusotoError::Credentials(CredentialsError::new("no credentials"));
+        assert!(!is_transient::<PutObjectError>(&credentials));
+        let validation = RusotoError::Validation("invalid bucket name".to_string());
+        assert!(!is_transient::<PutObjectError>(&validation));
+        assert_eq!(error_kind(&unknown(403, Some("AccessDenied"))), "client");
+        assert!(!is_transient(&unknown(403, Some("AccessDenied"))));
+        assert!(!is_transient(&unknown(404, Some("NoSuchBucket"))));
+    }
+
+    #[test]
+    fn retries_untyped_server_errors_and_throttling() {
+        assert_eq!(error_kind(&unknown(500, None)), "server");
+        assert_eq!(error_kind(&unknown(503, None)), "server");
+        assert_eq!(error_kind(&unknown(429, None)), "throttled");
+        assert!(is_transient(&unknown(500, Some("InternalError"))));
+        assert!(is_transient(&unknown(503, Some("ServiceUnavailable"))));
+        assert!(is_transient(&unknown(429, None)));
+    }
+}
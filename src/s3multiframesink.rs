@@ -4,29 +4,291 @@
 // option. This file may not be copied, modified, or distributed
 // except according to those terms.
 
-use crate::put_object_handler::PutObjectHandler;
+use crate::canceller::{Canceller, Unlocked};
+use crate::date_partition::{self, Granularity};
+use crate::enum_type;
+use crate::frame_body::FrameBody;
+use crate::jpeg_reframer::JpegReframer;
+use crate::key_template::{KeyFields, KeyTemplate};
+use crate::rate_limiter::RateLimiter;
+use crate::region;
+use crate::request_dispatcher::{self, RequestDispatcher};
+use crate::retry_handler::{Backoff, PutObjectHandler, S3RetryHandler};
+use crate::tar_archive::TarArchive;
+use crate::timestamp_format::TimestampFormat;
+use crate::upload_queue::UploadQueue;
+use flate2::write::GzEncoder;
+use flate2::Compression;
+use futures::future;
 use futures_retry::FutureRetry;
 use glib::subclass;
 use glib::subclass::prelude::*;
 use gst::prelude::*;
 use gst::subclass::prelude::*;
+use gst_base::prelude::*;
 use gst_base::subclass::prelude::*;
 use once_cell::sync::Lazy;
-use rusoto_core::{Region, RusotoError};
+use rusoto_core::credential::{
+    AutoRefreshingProvider, DefaultCredentialsProvider, ProfileProvider, StaticProvider,
+};
+use rusoto_core::{HttpClient, Region, RusotoError};
 use rusoto_s3::{
-    CreateBucketConfiguration, CreateBucketError, CreateBucketRequest, PutObjectRequest, S3Client,
-    S3,
+    AbortMultipartUploadRequest, CompleteMultipartUploadRequest, CompletedMultipartUpload,
+    CompletedPart, CreateBucketConfiguration, CreateBucketError, CreateBucketRequest,
+    CreateMultipartUploadRequest, Delete, DeleteBucketRequest, DeleteObjectsRequest,
+    GetBucketLocationRequest, GetObjectLockConfigurationRequest, HeadBucketError,
+    HeadBucketRequest, ListObjectsV2Request, ObjectIdentifier, PutObjectError, PutObjectRequest,
+    S3Client, UploadPartRequest, S3,
 };
+use rusoto_sts::{StsAssumeRoleSessionCredentialsProvider, StsClient};
+use serde_json::json;
+use sha2::{Digest, Sha256};
+use std::collections::{HashMap, VecDeque};
+use std::fmt::{self, Write as _};
+use std::io::Write;
 use std::str::FromStr;
-use std::sync::Mutex;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::time::{Duration, Instant, SystemTime, UNIX_EPOCH};
 use tokio::runtime;
 
-#[derive(Debug)]
+const DEFAULT_KEY_SEPARATOR: &str = "/";
+const DEFAULT_CREATE_BUCKET: bool = true;
+const DEFAULT_ASYNC_UPLOADS: bool = false;
+const DEFAULT_MAX_IN_FLIGHT: u32 = 4;
+const DEFAULT_VERIFY_INTEGRITY: bool = false;
+const DEFAULT_WRITE_METADATA: bool = true;
+const DEFAULT_DRY_RUN: bool = false;
+const DEFAULT_RUNTIME_THREADS: u32 = 0;
+const DEFAULT_BATCH_SIZE: u32 = 1;
+const DEFAULT_ACCEPT_EXISTING_BUCKET: bool = true;
+const DEFAULT_START_INDEX: u64 = 1;
+const DEFAULT_WRITE_MANIFEST: bool = false;
+const DEFAULT_MAX_UPLOADS_PER_SECOND: u32 = 0;
+const DEFAULT_AUTO_REGION: bool = false;
+const DEFAULT_REQUEST_TIMEOUT_MS: u32 = 0;
+const DEFAULT_DELETE_ON_STOP: bool = false;
+// The size of each part of a multipart upload, raised for objects that would
+// otherwise exceed S3's limit of 10000 parts
+const MULTIPART_PART_SIZE: usize = 16 * 1024 * 1024;
+const MULTIPART_MAX_PARTS: usize = 10_000;
+// The number of recent uploads averaged for avg-upload-ms
+const UPLOAD_TIMES_WINDOW: usize = 32;
+const DEFAULT_ALLOW_EMPTY_FRAMES: bool = false;
+const DEFAULT_MULTIPART_THRESHOLD: u64 = 100 * 1024 * 1024;
+const DEFAULT_MAX_TOTAL_BYTES: u64 = 0;
+const DEFAULT_NO_OVERWRITE: bool = false;
+const DEFAULT_PREFLIGHT_CHECK: bool = true;
+const DEFAULT_COMPRESS: bool = false;
+const DEFAULT_DATE_PARTITION: bool = false;
+const DEFAULT_DATE_PARTITION_GRANULARITY: Granularity = Granularity::Hour;
+const DEFAULT_DROP_ON_BACKPRESSURE: bool = false;
+const DEFAULT_RESOLUTION_IN_KEY: bool = false;
+const DEFAULT_AUTO_KEY: bool = false;
+const DEFAULT_REFRAME_JPEG: bool = false;
+const DEFAULT_TIMESTAMP_SOURCE: TimestampSource = TimestampSource::Pts;
+const DEFAULT_MAX_CONSECUTIVE_FAILURES: u32 = 1;
+const DEFAULT_HASH_IN_KEY: bool = false;
+const DEFAULT_SKIP_DUPLICATE_FRAMES: bool = false;
+const DEFAULT_PAUSED: bool = false;
+const DEFAULT_PAUSED_QUEUE_SIZE: u32 = 100;
+const DEFAULT_RUNTIME_STACK_SIZE: u32 = 0;
+const DEFAULT_FRAME_INTERVAL: u32 = 1;
+const DEFAULT_FILENAME_DIGITS: u32 = 10;
+const DEFAULT_WRITE_SIDECAR: bool = false;
+const DEFAULT_SIDECAR_FAILURE_FATAL: bool = true;
+const DEFAULT_TOLERATE_CREATE_BUCKET_ERRORS: bool = false;
+const DEFAULT_RESET_ON_SEGMENT: bool = false;
+const DEFAULT_ZERO_COPY_THRESHOLD: u64 = 0;
+const DEFAULT_THROTTLE_BASE_MS: u32 = 100;
+const DEFAULT_MIRROR_MODE: MirrorMode = MirrorMode::All;
+const DEFAULT_RESUME: bool = false;
+const DEFAULT_RETRY_ATTEMPTS: u32 = 5;
+const DEFAULT_RETRY_JITTER_BASE_MS: u32 = 5;
+const DEFAULT_RETRY_JITTER_MAX_MS: u32 = 32_000;
+
+const STORAGE_CLASSES: [&str; 9] = [
+    "STANDARD",
+    "REDUCED_REDUNDANCY",
+    "STANDARD_IA",
+    "ONEZONE_IA",
+    "INTELLIGENT_TIERING",
+    "GLACIER",
+    "GLACIER_IR",
+    "DEEP_ARCHIVE",
+    "OUTPOSTS",
+];
+
+const OBJECT_ACLS: [&str; 7] = [
+    "private",
+    "public-read",
+    "public-read-write",
+    "authenticated-read",
+    "aws-exec-read",
+    "bucket-owner-read",
+    "bucket-owner-full-control",
+];
+
+const BUCKET_ACLS: [&str; 4] = [
+    "private",
+    "public-read",
+    "public-read-write",
+    "authenticated-read",
+];
+
+#[derive(Debug, Clone)]
 struct Settings {
     bucket: Option<String>,
     key: Option<String>,
+    key_template: Option<String>,
     extension: Option<String>,
     region: Region,
+    endpoint: Option<String>,
+    access_key: Option<String>,
+    secret_key: Option<String>,
+    create_bucket: bool,
+    async_uploads: bool,
+    max_in_flight: u32,
+    retry_attempts: u32,
+    retry_jitter_base_ms: u32,
+    retry_jitter_max_ms: u32,
+    server_side_encryption: Option<String>,
+    sse_kms_key_id: Option<String>,
+    storage_class: Option<String>,
+    tags: Option<String>,
+    object_acl: Option<String>,
+    bucket_acl: Option<String>,
+    verify_integrity: bool,
+    write_metadata: bool,
+    dry_run: bool,
+    profile: Option<String>,
+    content_disposition: Option<String>,
+    runtime_threads: u32,
+    batch_size: u32,
+    accept_existing_bucket: bool,
+    start_index: u64,
+    write_manifest: bool,
+    max_uploads_per_second: u32,
+    auto_region: bool,
+    meta_reference: Option<String>,
+    request_timeout_ms: u32,
+    delete_on_stop: bool,
+    assume_role_arn: Option<String>,
+    assume_role_external_id: Option<String>,
+    key_separator: String,
+    allow_empty_frames: bool,
+    multipart_threshold: u64,
+    content_encoding: Option<String>,
+    max_total_bytes: u64,
+    no_overwrite: bool,
+    key_extension: Option<String>,
+    preflight_check: bool,
+    compress: bool,
+    date_partition: bool,
+    date_partition_granularity: Granularity,
+    custom_headers: Option<String>,
+    drop_on_backpressure: bool,
+    resolution_in_key: bool,
+    request_payer: Option<String>,
+    auto_key: bool,
+    // The prefix generated by auto-key, used when key is unset
+    generated_key: Option<String>,
+    reframe_jpeg: bool,
+    bucket_grant_read: Option<String>,
+    bucket_grant_full_control: Option<String>,
+    timestamp_source: TimestampSource,
+    max_consecutive_failures: u32,
+    hash_in_key: bool,
+    skip_duplicate_frames: bool,
+    cache_control: Option<String>,
+    paused: bool,
+    paused_queue_size: u32,
+    runtime_stack_size: u32,
+    object_lock_mode: Option<ObjectLockMode>,
+    object_lock_retain_until: Option<String>,
+    frame_interval: u32,
+    filename_digits: u32,
+    write_sidecar: bool,
+    sidecar_failure_fatal: bool,
+    key_suffix: Option<String>,
+    expires: Option<Expires>,
+    tolerate_create_bucket_errors: bool,
+    reset_on_segment: bool,
+    zero_copy_threshold: u64,
+    throttle_base_ms: u32,
+    mirror_bucket: Option<String>,
+    mirror_region: Option<Region>,
+    mirror_mode: MirrorMode,
+    timestamp_format: Option<TimestampFormat>,
+    resume: bool,
+}
+
+impl Settings {
+    // S3 requires Content-MD5 on uploads with an object lock retention
+    fn needs_content_md5(&self) -> bool {
+        self.verify_integrity || self.object_lock_mode.is_some()
+    }
+
+    // The key property without trailing separators or slashes, so that a key of
+    // `captures/` doesn't produce `captures//frame0000000001.png`
+    fn key_prefix(&self) -> &str {
+        let key = self
+            .key
+            .as_ref()
+            .or(self.generated_key.as_ref())
+            .expect("Key should be set by start time");
+        let mut prefix = key.as_str();
+        loop {
+            let trimmed = prefix.trim_end_matches('/');
+            let trimmed = if self.key_separator.is_empty() {
+                trimmed
+            } else {
+                trimmed.trim_end_matches(self.key_separator.as_str())
+            };
+            if trimmed == prefix {
+                return prefix;
+            }
+            prefix = trimmed;
+        }
+    }
+
+    fn object_key(&self, name: &str) -> String {
+        format!("{}{}{}", self.key_prefix(), self.key_separator, name)
+    }
+
+    fn retry_handler<E>(
+        &self,
+        element: &gst::Element,
+        frame_num: Option<u64>,
+        operation: String,
+    ) -> S3RetryHandler<E> {
+        S3RetryHandler::new(
+            self.retry_attempts as usize,
+            element.clone(),
+            *CAT,
+            operation,
+            frame_num,
+            Backoff {
+                base: Duration::from_millis(self.retry_jitter_base_ms.into()),
+                throttle_base: Duration::from_millis(self.throttle_base_ms.into()),
+                max: Duration::from_millis(self.retry_jitter_max_ms.into()),
+            },
+        )
+    }
+
+    // A timestamp as it appears in keys and metadata
+    fn render_timestamp(&self, timestamp_ms: u128) -> String {
+        match self.timestamp_format {
+            Some(ref format) => format.render(timestamp_ms),
+            None => timestamp_ms.to_string(),
+        }
+    }
+
+    fn default_key_template(&self) -> String {
+        format!(
+            "{{prefix}}{}frame{{frame:0{}}}{{suffix}}",
+            self.key_separator, self.filename_digits
+        )
+    }
 }
 
 impl Default for Settings {
@@ -34,13 +296,91 @@ impl Default for Settings {
         Settings {
             bucket: Default::default(),
             key: Default::default(),
+            key_template: Default::default(),
             extension: Default::default(),
             region: Region::default(),
+            endpoint: Default::default(),
+            access_key: Default::default(),
+            secret_key: Default::default(),
+            create_bucket: DEFAULT_CREATE_BUCKET,
+            async_uploads: DEFAULT_ASYNC_UPLOADS,
+            max_in_flight: DEFAULT_MAX_IN_FLIGHT,
+            retry_attempts: DEFAULT_RETRY_ATTEMPTS,
+            retry_jitter_base_ms: DEFAULT_RETRY_JITTER_BASE_MS,
+            retry_jitter_max_ms: DEFAULT_RETRY_JITTER_MAX_MS,
+            server_side_encryption: Default::default(),
+            sse_kms_key_id: Default::default(),
+            storage_class: Default::default(),
+            tags: Default::default(),
+            object_acl: Default::default(),
+            bucket_acl: Default::default(),
+            verify_integrity: DEFAULT_VERIFY_INTEGRITY,
+            write_metadata: DEFAULT_WRITE_METADATA,
+            dry_run: DEFAULT_DRY_RUN,
+            profile: Default::default(),
+            content_disposition: Default::default(),
+            runtime_threads: DEFAULT_RUNTIME_THREADS,
+            batch_size: DEFAULT_BATCH_SIZE,
+            accept_existing_bucket: DEFAULT_ACCEPT_EXISTING_BUCKET,
+            start_index: DEFAULT_START_INDEX,
+            write_manifest: DEFAULT_WRITE_MANIFEST,
+            max_uploads_per_second: DEFAULT_MAX_UPLOADS_PER_SECOND,
+            auto_region: DEFAULT_AUTO_REGION,
+            meta_reference: Default::default(),
+            request_timeout_ms: DEFAULT_REQUEST_TIMEOUT_MS,
+            delete_on_stop: DEFAULT_DELETE_ON_STOP,
+            assume_role_arn: Default::default(),
+            assume_role_external_id: Default::default(),
+            key_separator: DEFAULT_KEY_SEPARATOR.to_string(),
+            allow_empty_frames: DEFAULT_ALLOW_EMPTY_FRAMES,
+            multipart_threshold: DEFAULT_MULTIPART_THRESHOLD,
+            content_encoding: Default::default(),
+            max_total_bytes: DEFAULT_MAX_TOTAL_BYTES,
+            no_overwrite: DEFAULT_NO_OVERWRITE,
+            key_extension: Default::default(),
+            preflight_check: DEFAULT_PREFLIGHT_CHECK,
+            compress: DEFAULT_COMPRESS,
+            date_partition: DEFAULT_DATE_PARTITION,
+            date_partition_granularity: DEFAULT_DATE_PARTITION_GRANULARITY,
+            custom_headers: Default::default(),
+            drop_on_backpressure: DEFAULT_DROP_ON_BACKPRESSURE,
+            resolution_in_key: DEFAULT_RESOLUTION_IN_KEY,
+            request_payer: Default::default(),
+            auto_key: DEFAULT_AUTO_KEY,
+            generated_key: Default::default(),
+            reframe_jpeg: DEFAULT_REFRAME_JPEG,
+            bucket_grant_read: Default::default(),
+            bucket_grant_full_control: Default::default(),
+            timestamp_source: DEFAULT_TIMESTAMP_SOURCE,
+            max_consecutive_failures: DEFAULT_MAX_CONSECUTIVE_FAILURES,
+            hash_in_key: DEFAULT_HASH_IN_KEY,
+            skip_duplicate_frames: DEFAULT_SKIP_DUPLICATE_FRAMES,
+            cache_control: Default::default(),
+            paused: DEFAULT_PAUSED,
+            paused_queue_size: DEFAULT_PAUSED_QUEUE_SIZE,
+            runtime_stack_size: DEFAULT_RUNTIME_STACK_SIZE,
+            object_lock_mode: None,
+            object_lock_retain_until: Default::default(),
+            frame_interval: DEFAULT_FRAME_INTERVAL,
+            filename_digits: DEFAULT_FILENAME_DIGITS,
+            write_sidecar: DEFAULT_WRITE_SIDECAR,
+            sidecar_failure_fatal: DEFAULT_SIDECAR_FAILURE_FATAL,
+            key_suffix: Default::default(),
+            expires: None,
+            tolerate_create_bucket_errors: DEFAULT_TOLERATE_CREATE_BUCKET_ERRORS,
+            reset_on_segment: DEFAULT_RESET_ON_SEGMENT,
+            zero_copy_threshold: DEFAULT_ZERO_COPY_THRESHOLD,
+            throttle_base_ms: DEFAULT_THROTTLE_BASE_MS,
+            mirror_bucket: Default::default(),
+            mirror_region: None,
+            mirror_mode: DEFAULT_MIRROR_MODE,
+            timestamp_format: None,
+            resume: DEFAULT_RESUME,
         }
     }
 }
 
-static PROPERTIES: [subclass::Property; 4] = [
+static PROPERTIES: [subclass::Property; 85] = [
     subclass::Property("bucket", |name| {
         glib::ParamSpec::string(
             name,
@@ -59,15 +399,25 @@ static PROPERTIES: [subclass::Property; 4] = [
             glib::ParamFlags::READWRITE,
         )
     }),
-    subclass::Property("region", |name| {
+    subclass::Property("key-template", |name| {
         glib::ParamSpec::string(
             name,
-            "AWS Region",
-            "An AWS region (e.g. eu-west-2).",
+            "Object Key Template",
+            "A template for each object's key, supporting {prefix}, {frame:06}, {pts}, {timestamp} and {suffix}",
             None,
             glib::ParamFlags::READWRITE,
         )
     }),
+    subclass::Property("region", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "AWS Region",
+            "An AWS region (e.g. eu-west-2), or custom for S3-compatible services at endpoint",
+            region::region_type(),
+            region::to_enum_value(&Region::UsEast1),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
     subclass::Property("extension", |name| {
         glib::ParamSpec::string(
             name,
@@ -77,312 +427,4414 @@ static PROPERTIES: [subclass::Property; 4] = [
             glib::ParamFlags::READWRITE,
         )
     }),
-];
-
-static RUNTIME: Lazy<runtime::Runtime> = Lazy::new(|| {
-    runtime::Builder::new()
-        .threaded_scheduler()
-        .enable_all()
-        .thread_name("gst-s3sink-runtime")
-        .build()
-        .unwrap()
-});
-
-enum State {
-    Stopped,
-    Started { frame_num: u64, s3client: S3Client },
-}
-
-impl Default for State {
-    fn default() -> State {
-        State::Stopped
-    }
-}
-
-pub struct S3MultiFrameSink {
-    settings: Mutex<Settings>,
-    state: Mutex<State>,
-}
-
-static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
-    gst::DebugCategory::new(
-        "rsS3MultiFrameSink",
-        gst::DebugColorFlags::empty(),
-        Some("File Sink"),
-    )
-});
-
-impl ObjectSubclass for S3MultiFrameSink {
-    const NAME: &'static str = "RusotosS3MultiFrameSink";
-    type ParentType = gst_base::BaseSink;
-    type Instance = gst::subclass::ElementInstanceStruct<Self>;
-    type Class = subclass::simple::ClassStruct<Self>;
-
-    glib_object_subclass!();
-
-    fn class_init(klass: &mut subclass::simple::ClassStruct<Self>) {
-        klass.set_metadata(
-            "s3 sink intended for png frame data",
-            "Sink/S3",
-            "Writes individual video frames to an S3 bucket",
-            "This has to be provided",
-        );
-
-        let image_caps = vec![
-            create_image_cap("image/jpeg"),
-            create_image_cap("image/png"),
-            create_image_cap("image/tiff"),
-        ]
-        .into_iter()
-        .fold(create_image_cap("image/gif"), |a, b| gst::Caps::merge(a, b));
-
-        let sink_pad_template = gst::PadTemplate::new(
-            "sink",
-            gst::PadDirection::Sink,
-            gst::PadPresence::Always,
-            &image_caps,
+    subclass::Property("endpoint", |name| {
+        glib::ParamSpec::string(
+            name,
+            "S3 Endpoint",
+            "A custom endpoint for S3-compatible services (e.g. http://localhost:9000)",
+            None,
+            glib::ParamFlags::READWRITE,
         )
-        .unwrap();
-        klass.add_pad_template(sink_pad_template);
-        klass.install_properties(&PROPERTIES);
-    }
-
-    fn new() -> Self {
-        Self {
-            settings: Mutex::new(Default::default()),
-            state: Mutex::new(Default::default()),
-        }
-    }
-}
-
-fn create_image_cap(name: &str) -> gst::Caps {
-    gst::Caps::new_simple(
-        name,
-        &[
-            ("width", &gst::IntRange::<i32>::new(0, i32::MAX)),
-            ("height", &gst::IntRange::<i32>::new(0, i32::MAX)),
-            (
-                "framerate",
-                &gst::FractionRange::new(gst::Fraction::new(0, 1), gst::Fraction::new(i32::MAX, 1)),
-            ),
-        ],
-    )
-}
-
-impl ObjectImpl for S3MultiFrameSink {
-    glib_object_impl!();
-
-    fn set_property(&self, _: &glib::Object, id: usize, value: &glib::Value) {
-        let prop = &PROPERTIES[id];
-        let mut settings = self.settings.lock().unwrap();
-        match *prop {
-            subclass::Property("bucket", ..) => {
-                settings.bucket = value.get::<String>().expect("Type checked upstream");
-            }
-            subclass::Property("key", ..) => {
-                settings.key = value.get::<String>().expect("Type checked upstream");
-            }
-            subclass::Property("extension", ..) => {
-                settings.extension = value.get::<String>().expect("Type checked upstream")
-            }
-            subclass::Property("region", ..) => {
-                settings.region = Region::from_str(
-                    &value
-                        .get::<String>()
-                        .expect("Type checked upstream")
-                        .expect("region value not provided"),
-                )
-                .expect("invalid region provided");
-            }
-            _ => unimplemented!(),
-        };
-    }
-
-    fn get_property(&self, _: &glib::Object, id: usize) -> Result<glib::Value, ()> {
-        let prop = &PROPERTIES[id];
-
-        let settings = self.settings.lock().unwrap();
-        match *prop {
-            subclass::Property("bucket", ..) => {
-                let bucket = settings
-                    .bucket
-                    .as_ref()
-                    .map(|location| location.to_string());
-                Ok(bucket.to_value())
-            }
-            subclass::Property("key", ..) => {
-                let key = settings.key.as_ref().map(|location| location.to_string());
-                Ok(key.to_value())
-            }
-            subclass::Property("extension", ..) => {
-                let extension = settings
-                    .extension
-                    .as_ref()
-                    .map(|location| location.to_string());
-                Ok(extension.to_value())
-            }
-            subclass::Property("region", ..) => Ok(settings.region.name().to_value()),
-            _ => unimplemented!(),
-        }
-    }
-}
-
-impl ElementImpl for S3MultiFrameSink {}
-
-impl BaseSinkImpl for S3MultiFrameSink {
-    fn start(&self, element: &gst_base::BaseSink) -> Result<(), gst::ErrorMessage> {
-        let mut state = self.state.lock().unwrap();
-        if let State::Started { .. } = *state {
-            unreachable!("S3MultiFrameSink already started");
-        }
-
-        let settings = self.settings.lock().unwrap();
-        let s3client = S3Client::new(settings.region.clone());
-        drop(settings);
-        self.create_bucket_if_extant(&s3client)?;
-
-        *state = State::Started {
-            frame_num: 0,
-            s3client,
-        };
-        gst_info!(CAT, obj: element, "Started");
-
-        Ok(())
-    }
-
-    fn stop(&self, element: &gst_base::BaseSink) -> Result<(), gst::ErrorMessage> {
-        let mut state = self.state.lock().unwrap();
-        if let State::Stopped = *state {
-            return Err(gst_error_msg!(
-                gst::ResourceError::Settings,
-                ["S3MultiFrameSink not started"]
-            ));
-        }
-        *state = State::Stopped;
-        gst_info!(CAT, obj: element, "Stopped");
-
-        Ok(())
-    }
-
-
-    fn render(
-        &self,
-        element: &gst_base::BaseSink,
-        buffer: &gst::Buffer,
-    ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        let mut state = self.state.lock().unwrap();
-        let (frame_num, s3client) = match *state {
-            State::Started {
-                ref mut frame_num,
-                ref s3client,
-            } => (frame_num, s3client),
-            State::Stopped => {
-                gst_element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
-                return Err(gst::FlowError::Error);
-            }
-        };
-
-        gst_trace!(CAT, obj: element, "Rendering {:?}", buffer);
-
-        let map = buffer.map_readable().map_err(|_| {
-            gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
-            gst::FlowError::Error
-        })?;
-        let vec: Vec<u8> = map.as_ref().to_vec();
-        self.upload_image_frame(s3client, frame_num, vec)
-    }
-}
-
-pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
-    gst::Element::register(
-        Some(plugin),
-        "s3multiframesink",
-        gst::Rank::None,
-        S3MultiFrameSink::get_type(),
-    )
-}
-
-impl S3MultiFrameSink {
-    fn upload_image_frame(
-        &self,
-        s3client: &S3Client,
-        frame_num: &mut u64,
-        vec: Vec<u8>,
-    ) -> Result<gst::FlowSuccess, gst::FlowError> {
-        *frame_num += 1;
-        let settings = self.settings.lock().unwrap();
-        let bucket = settings.bucket.as_ref().unwrap().clone();
-        let key = settings.key.as_ref().unwrap().clone();
-        let extension = settings.extension.as_ref().unwrap().clone();
-        RUNTIME
-            .handle()
-            .block_on(FutureRetry::new(
-                || {
-                    let put_request = S3MultiFrameSink::create_put_object_request(
-                        *frame_num, &vec, &bucket, &key, &extension,
-                    );
-                    s3client.put_object(put_request)
-                },
-                PutObjectHandler::new(5, *frame_num),
-            ))
-            .map(|_| gst::FlowSuccess::Ok)
-            .map_err(|_| gst::FlowError::Error)
-    }
-
-    fn create_put_object_request(
-        frame_count: u64,
-        vec: &Vec<u8>,
-        bucket: &str,
-        key: &str,
-        file_extension: &str,
-    ) -> PutObjectRequest {
-        PutObjectRequest {
-            bucket: bucket.to_owned(),
-            key: format!(
-                "{}/frame{:0>2}.{}",
-                key,
-                frame_count.clone(),
-                file_extension
-            ),
-            body: Some(vec.clone().into()),
-            ..Default::default()
-        }
-    }
-
-    fn create_bucket_if_extant(&self, s3client: &S3Client) -> Result<(), gst::ErrorMessage> {
-        let settings = self.settings.lock().unwrap();
-        let bucket = settings
-            .bucket
-            .as_ref()
-            .expect("Bucket should be set by start time")
-            .clone();
-        RUNTIME.handle().block_on(async {
-            let bucket_creation = s3client
-                .create_bucket(CreateBucketRequest {
-                    acl: None,
-                    bucket,
-                    create_bucket_configuration: Some(CreateBucketConfiguration {
-                        location_constraint: Some(settings.region.name().to_string()),
-                    }),
-                    grant_full_control: None,
-                    grant_read: None,
-                    grant_read_acp: None,
-                    grant_write: None,
-                    grant_write_acp: None,
-                    object_lock_enabled_for_bucket: None,
-                })
-                .await;
-
-            bucket_creation.map(|_| ()).or_else(|error| {
-                if let RusotoError::Service(CreateBucketError::BucketAlreadyOwnedByYou(_)) = error {
-                    Ok(())
-                } else {
-                    Err(gst_error_msg!(
-                        gst::ResourceError::Settings,
-                        [&format!("{}", error)]
-                    ))
-                }
-            })
+    }),
+    subclass::Property("access-key", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Access Key",
+            "An AWS access key ID, used together with secret-key instead of the default credential chain",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("secret-key", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Secret Key",
+            "An AWS secret access key, used together with access-key instead of the default credential chain",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("create-bucket", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Create Bucket",
+            "Whether to create the bucket on start if it doesn't already exist",
+            DEFAULT_CREATE_BUCKET,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("async-uploads", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Async Uploads",
+            "Whether to upload frames in the background instead of blocking each render",
+            DEFAULT_ASYNC_UPLOADS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("max-in-flight", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Max In Flight",
+            "The maximum number of pending background uploads before render blocks",
+            1,
+            u32::MAX,
+            DEFAULT_MAX_IN_FLIGHT,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("retry-attempts", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Retry Attempts",
+            "The number of times a failed upload is retried (0 = never retry)",
+            0,
+            u32::MAX,
+            DEFAULT_RETRY_ATTEMPTS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("retry-jitter-base-ms", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Retry Jitter Base",
+            "The base delay in milliseconds of the exponential backoff between retries",
+            0,
+            u32::MAX,
+            DEFAULT_RETRY_JITTER_BASE_MS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("retry-jitter-max-ms", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Retry Jitter Max",
+            "The maximum delay in milliseconds of the exponential backoff between retries",
+            0,
+            u32::MAX,
+            DEFAULT_RETRY_JITTER_MAX_MS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("server-side-encryption", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Server-Side Encryption",
+            "The server-side encryption algorithm for uploaded objects (AES256 or aws:kms)",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("sse-kms-key-id", |name| {
+        glib::ParamSpec::string(
+            name,
+            "SSE KMS Key ID",
+            "The KMS key used when server-side-encryption is aws:kms",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("storage-class", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Storage Class",
+            "The S3 storage class for uploaded objects (e.g. STANDARD_IA)",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("tags", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Object Tags",
+            "Tags for uploaded objects in URL-encoded form (e.g. env=prod&job=camera7)",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("object-acl", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Object ACL",
+            "The canned ACL for uploaded objects (e.g. public-read)",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("bucket-acl", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Bucket ACL",
+            "The canned ACL for the bucket when it is created (e.g. private)",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("verify-integrity", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Verify Integrity",
+            "Whether to send a Content-MD5 so S3 verifies each uploaded object",
+            DEFAULT_VERIFY_INTEGRITY,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("write-metadata", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Write Metadata",
+            "Whether to store the frame number and PTS as object metadata",
+            DEFAULT_WRITE_METADATA,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("dry-run", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Dry run",
+            "Log the uploads that would be made without contacting S3",
+            DEFAULT_DRY_RUN,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("profile", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Profile",
+            "AWS credentials profile to use instead of the default credential chain",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("content-disposition", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Content-Disposition",
+            "Content-Disposition header for each object, may contain key template tokens",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("runtime-threads", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Runtime Threads",
+            "Number of worker threads for uploads, 0 for one per CPU core",
+            0,
+            u32::MAX,
+            DEFAULT_RUNTIME_THREADS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("batch-size", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Batch Size",
+            "Number of frames bundled into each uploaded tar archive, 1 uploads frames individually",
+            1,
+            u32::MAX,
+            DEFAULT_BATCH_SIZE,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("accept-existing-bucket", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Accept Existing Bucket",
+            "Continue when the bucket already exists but is owned by another account",
+            DEFAULT_ACCEPT_EXISTING_BUCKET,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("start-index", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Start Index",
+            "Frame number of the first frame after start, for resuming a sequence",
+            0,
+            u64::MAX,
+            DEFAULT_START_INDEX,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("write-manifest", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Write Manifest",
+            "Upload a JSON index of every frame key, PTS and size when the element stops",
+            DEFAULT_WRITE_MANIFEST,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("max-uploads-per-second", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Max Uploads Per Second",
+            "Maximum number of uploads started per second, 0 for unlimited",
+            0,
+            u32::MAX,
+            DEFAULT_MAX_UPLOADS_PER_SECOND,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("auto-region", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Auto Region",
+            "Look up the bucket's region on start and use it instead of the region property",
+            DEFAULT_AUTO_REGION,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("meta-reference", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Meta Reference",
+            "Reference caps name of the buffer timestamp meta used for the {meta} key token",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("request-timeout-ms", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Request Timeout",
+            "Timeout in milliseconds for each S3 request, 0 for no timeout",
+            0,
+            u32::MAX,
+            DEFAULT_REQUEST_TIMEOUT_MS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("delete-on-stop", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Delete On Stop",
+            "Delete the bucket and everything in it on stop, if the element created it",
+            DEFAULT_DELETE_ON_STOP,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("assume-role-arn", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Assume Role ARN",
+            "ARN of an IAM role to assume through STS for uploads",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("assume-role-external-id", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Assume Role External ID",
+            "External ID to pass when assuming assume-role-arn",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("key-separator", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Key Separator",
+            "Separator between the key prefix and the name of each object",
+            Some(DEFAULT_KEY_SEPARATOR),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("allow-empty-frames", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Allow Empty Frames",
+            "Upload zero-byte buffers instead of skipping them",
+            DEFAULT_ALLOW_EMPTY_FRAMES,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("multipart-threshold", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Multipart Threshold",
+            "Objects larger than this many bytes are uploaded in parts, 0 to never use multipart uploads",
+            0,
+            u64::MAX,
+            DEFAULT_MULTIPART_THRESHOLD,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("content-encoding", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Content-Encoding",
+            "Content-Encoding header for each object, e.g. gzip for pre-compressed frames",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("max-total-bytes", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Max Total Bytes",
+            "Stop accepting frames once this many bytes have been uploaded, 0 for no limit",
+            0,
+            u64::MAX,
+            DEFAULT_MAX_TOTAL_BYTES,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("no-overwrite", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "No Overwrite",
+            "Skip frames whose key already exists in the bucket instead of replacing them",
+            DEFAULT_NO_OVERWRITE,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("key-extension", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Key Extension",
+            "Suffix appended to each object key including any dot, e.g. .PNG, or empty for none",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("preflight-check", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Preflight Check",
+            "Check on start that the bucket is reachable with the configured credentials, region and endpoint",
+            DEFAULT_PREFLIGHT_CHECK,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("compress", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Compress",
+            "Gzip each frame before upload, appending .gz to its key and setting Content-Encoding",
+            DEFAULT_COMPRESS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("date-partition", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Date Partition",
+            "Insert the current UTC date into each key after the prefix, e.g. year=2024/month=01/day=15/hour=09",
+            DEFAULT_DATE_PARTITION,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("date-partition-granularity", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Date Partition Granularity",
+            "The finest unit of date-partition, one of day, hour or minute",
+            date_partition::granularity_type(),
+            DEFAULT_DATE_PARTITION_GRANULARITY.to_enum_value(),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("custom-headers", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Custom Headers",
+            "Extra HTTP headers for every S3 request, as Name:Value;Name2:Value2",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("drop-on-backpressure", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Drop On Backpressure",
+            "With async-uploads, drop frames while max-in-flight uploads are pending instead of blocking",
+            DEFAULT_DROP_ON_BACKPRESSURE,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("resolution-in-key", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Resolution In Key",
+            "Insert the negotiated WIDTHxHEIGHT into each key after the prefix",
+            DEFAULT_RESOLUTION_IN_KEY,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("request-payer", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Request Payer",
+            "Set to requester to upload to requester-pays buckets",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("auto-key", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Auto Key",
+            "Generate a capture-<timestamp> key prefix on start when key is unset",
+            DEFAULT_AUTO_KEY,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("reframe-jpeg", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Reframe JPEG",
+            "Split image/jpeg input on SOI and EOI markers, for streams whose buffers aren't whole images",
+            DEFAULT_REFRAME_JPEG,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("bucket-grant-read", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Bucket Grant Read",
+            "Grantees allowed to list the bucket when it is created, e.g. id=<canonical user id>",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("bucket-grant-full-control", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Bucket Grant Full Control",
+            "Grantees given full control of the bucket when it is created, e.g. id=<canonical user id>",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("timestamp-source", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Timestamp Source",
+            "The clock behind {timestamp} and date-partition, one of pts, wallclock or running-time",
+            *TIMESTAMP_SOURCE_TYPE,
+            DEFAULT_TIMESTAMP_SOURCE.to_enum_value(),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("max-consecutive-failures", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Max Consecutive Failures",
+            "Number of uploads in a row that may fail, after retries, before the pipeline errors out",
+            1,
+            u32::MAX,
+            DEFAULT_MAX_CONSECUTIVE_FAILURES,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("hash-in-key", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Hash In Key",
+            "Append the first 8 hex digits of the SHA-256 of each frame to its key",
+            DEFAULT_HASH_IN_KEY,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("skip-duplicate-frames", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Skip Duplicate Frames",
+            "Don't upload frames identical to the previous frame",
+            DEFAULT_SKIP_DUPLICATE_FRAMES,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("cache-control", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Cache-Control",
+            "Cache-Control header for each object, e.g. max-age=31536000, immutable",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("paused", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Paused",
+            "Hold frames instead of uploading them, resuming with the next frame once unset",
+            DEFAULT_PAUSED,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("paused-queue-size", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Paused Queue Size",
+            "The maximum number of uploads held while paused, beyond which frames are dropped (0 = drop every frame)",
+            0,
+            u32::MAX,
+            DEFAULT_PAUSED_QUEUE_SIZE,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("runtime-stack-size", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Runtime Stack Size",
+            "Stack size in bytes of each upload worker thread, 0 for the runtime default",
+            0,
+            u32::MAX,
+            DEFAULT_RUNTIME_STACK_SIZE,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("object-lock-mode", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Object Lock Mode",
+            "The object lock retention mode of uploads, GOVERNANCE or COMPLIANCE",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("object-lock-retain-until", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Object Lock Retain Until",
+            "The ISO 8601 date until which uploads are locked, such as 2030-01-01T00:00:00Z",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("frame-interval", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Frame Interval",
+            "Upload only every Nth frame, starting with the first, to subsample a high frame rate source",
+            1,
+            u32::MAX,
+            DEFAULT_FRAME_INTERVAL,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("filename-digits", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Filename Digits",
+            "The width frame numbers are zero-padded to in the default key format",
+            1,
+            20,
+            DEFAULT_FILENAME_DIGITS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("write-sidecar", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Write Sidecar",
+            "Upload a JSON object with the frame number, PTS and caps next to each frame",
+            DEFAULT_WRITE_SIDECAR,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("sidecar-failure-fatal", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Sidecar Failure Fatal",
+            "Whether a sidecar that fails to upload fails the pipeline, rather than only logging a warning",
+            DEFAULT_SIDECAR_FAILURE_FATAL,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("key-suffix", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Key Suffix",
+            "A string appended to each frame name before the extension, such as -camA, so several writers can share a prefix",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("expires", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Expires",
+            "The Expires header of uploads, as an RFC 1123 date or a number of seconds after each upload",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("tolerate-create-bucket-errors", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Tolerate Create Bucket Errors",
+            "Log failures to check for or create the bucket as warnings and start anyway",
+            DEFAULT_TOLERATE_CREATE_BUCKET_ERRORS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("reset-on-segment", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Reset On Segment",
+            "Restart frame numbers at each new segment, under a seg001-style segment prefix",
+            DEFAULT_RESET_ON_SEGMENT,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("zero-copy-threshold", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Zero Copy Threshold",
+            "Frames up to this many bytes are copied so their buffer is released at once, larger ones are streamed from it (0 = always stream)",
+            0,
+            u64::MAX,
+            DEFAULT_ZERO_COPY_THRESHOLD,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("throttle-base-ms", |name| {
+        glib::ParamSpec::uint(
+            name,
+            "Throttle Base",
+            "The base delay in milliseconds of the backoff after throttled requests, used instead of retry-jitter-base-ms",
+            0,
+            u32::MAX,
+            DEFAULT_THROTTLE_BASE_MS,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("mirror-bucket", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Mirror Bucket",
+            "A second bucket every upload is also written to, in parallel",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("mirror-region", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Mirror Region",
+            "The region of mirror-bucket (e.g. eu-west-1), when set to differ from region",
+            region::region_type(),
+            region::to_enum_value(&Region::UsEast1),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("mirror-mode", |name| {
+        glib::ParamSpec::enum_(
+            name,
+            "Mirror Mode",
+            "Whether uploads must reach all of the buckets or any of them with mirror-bucket, all or any",
+            *MIRROR_MODE_TYPE,
+            DEFAULT_MIRROR_MODE.to_enum_value(),
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("timestamp-format", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Timestamp Format",
+            "A strftime-style pattern for {timestamp}, such as %Y-%m-%dT%H:%M:%S.%3fZ, instead of Unix milliseconds",
+            None,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("resume", |name| {
+        glib::ParamSpec::boolean(
+            name,
+            "Resume",
+            "Whether to continue numbering after the highest frame already under the key on start",
+            DEFAULT_RESUME,
+            glib::ParamFlags::READWRITE,
+        )
+    }),
+    subclass::Property("frames-uploaded", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Frames Uploaded",
+            "The number of frames successfully uploaded since the element was started",
+            0,
+            u64::MAX,
+            0,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+    subclass::Property("avg-upload-ms", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Average Upload Time",
+            "The average time in milliseconds taken by the most recent uploads, including retries",
+            0,
+            u64::MAX,
+            0,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+    subclass::Property("last-key", |name| {
+        glib::ParamSpec::string(
+            name,
+            "Last Key",
+            "The key of the most recent successful upload since the element was started",
+            None,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+    subclass::Property("frames-dropped", |name| {
+        glib::ParamSpec::uint64(
+            name,
+            "Frames Dropped",
+            "The number of frames dropped by drop-on-backpressure or while paused since the element was started",
+            0,
+            u64::MAX,
+            0,
+            glib::ParamFlags::READABLE,
+        )
+    }),
+];
+
+struct Started {
+    // The number given to the next frame
+    frame_num: u64,
+    // Owned per element so its threads go away with the pipeline
+    runtime: runtime::Runtime,
+    // None in dry-run mode
+    s3client: Option<S3Client>,
+    // The client for mirror-bucket, if set
+    mirror_s3client: Option<S3Client>,
+    key_template: KeyTemplate,
+    content_disposition: Option<KeyTemplate>,
+    media_type: Option<String>,
+    // The negotiated caps as a string, for sidecars
+    caps: Option<String>,
+    video_info: Option<gst_video::VideoInfo>,
+    dimensions: Option<(u32, u32)>,
+    uploads: UploadQueue<UploadResult>,
+    batch_size: u64,
+    batch: Batch,
+    rate_limiter: Option<RateLimiter>,
+    // The bucket's region, as found by auto-region or else as configured
+    region: Region,
+    // Whether the bucket was created by this element, rather than pre-existing
+    bucket_created: bool,
+    // Entries for manifest.json, when enabled
+    manifest: Option<Vec<serde_json::Value>>,
+    // Bytes in successful uploads, counted against max-total-bytes
+    bytes_uploaded: u64,
+    // Uploads that failed for good, after any retries
+    uploads_failed: u64,
+    // Failed uploads since the last successful one
+    consecutive_failures: u32,
+    max_consecutive_failures: u32,
+    sidecar_failure_fatal: bool,
+    started_at: Instant,
+    // SHA-256 and number of the previous frame, for skip-duplicate-frames
+    last_frame_hash: Option<([u8; 32], u64)>,
+    // Reassembles whole images from JPEG input, when enabled
+    jpeg_reframer: Option<JpegReframer>,
+    // Uploads held back while paused, bounded by paused-queue-size
+    paused_frames: VecDeque<FrameUpload>,
+    // Frames seen since start, kept or not, for frame-interval
+    frames_seen: u64,
+    // Segments received since start, for reset-on-segment
+    segment_index: u64,
+    // Places the chosen timestamp-source on the calendar, for date-partition
+    clock_anchor: Option<ClockAnchor>,
+}
+
+#[allow(clippy::large_enum_variant)]
+enum State {
+    Stopped,
+    Started(Started),
+}
+
+impl Default for State {
+    fn default() -> State {
+        State::Stopped
+    }
+}
+
+// Counters read by the stats properties. They're kept out of the state, which
+// render holds for the length of an upload.
+#[derive(Default)]
+struct Stats {
+    frames_uploaded: u64,
+    // Durations of the most recent uploads, for avg-upload-ms
+    upload_times: VecDeque<Duration>,
+    last_key: Option<String>,
+    frames_dropped: u64,
+}
+
+pub struct S3MultiFrameSink {
+    settings: Mutex<Settings>,
+    state: Mutex<State>,
+    stats: Mutex<Stats>,
+    canceller: Canceller,
+}
+
+static CAT: Lazy<gst::DebugCategory> = Lazy::new(|| {
+    gst::DebugCategory::new(
+        "rsS3MultiFrameSink",
+        gst::DebugColorFlags::empty(),
+        Some("File Sink"),
+    )
+});
+
+impl ObjectSubclass for S3MultiFrameSink {
+    const NAME: &'static str = "RusotosS3MultiFrameSink";
+    type ParentType = gst_base::BaseSink;
+    type Instance = gst::subclass::ElementInstanceStruct<Self>;
+    type Class = subclass::simple::ClassStruct<Self>;
+
+    glib_object_subclass!();
+
+    fn class_init(klass: &mut subclass::simple::ClassStruct<Self>) {
+        klass.set_metadata(
+            "s3 sink intended for png frame data",
+            "Sink/S3",
+            "Writes individual video frames to an S3 bucket",
+            "This has to be provided",
+        );
+
+        let image_caps = vec![
+            create_image_cap("image/jpeg"),
+            create_image_cap("image/png"),
+            create_image_cap("image/tiff"),
+            create_image_cap("image/webp"),
+            create_image_cap("image/bmp"),
+            create_raw_cap(),
+        ]
+        .into_iter()
+        .fold(create_image_cap("image/gif"), |a, b| gst::Caps::merge(a, b));
+
+        let sink_pad_template = gst::PadTemplate::new(
+            "sink",
+            gst::PadDirection::Sink,
+            gst::PadPresence::Always,
+            &image_caps,
+        )
+        .unwrap();
+        klass.add_pad_template(sink_pad_template);
+        klass.install_properties(&PROPERTIES);
+        klass.add_signal(
+            "frame-uploaded",
+            glib::SignalFlags::RUN_LAST,
+            &[glib::Type::U64, glib::Type::String],
+            glib::Type::Unit,
+        );
+        klass.add_signal(
+            "bucket-created",
+            glib::SignalFlags::RUN_LAST,
+            &[glib::Type::String],
+            glib::Type::Unit,
+        );
+    }
+
+    fn new() -> Self {
+        Self {
+            settings: Mutex::new(Default::default()),
+            state: Mutex::new(Default::default()),
+            stats: Mutex::new(Default::default()),
+            canceller: Default::default(),
+        }
+    }
+}
+
+// Images from a parser or filesrc often carry no dimensions or framerate
+fn create_image_cap(name: &str) -> gst::Caps {
+    gst::Caps::new_simple(name, &[])
+}
+
+fn create_raw_cap() -> gst::Caps {
+    gst::Caps::new_simple(
+        "video/x-raw",
+        &[
+            ("width", &gst::IntRange::<i32>::new(0, i32::MAX)),
+            ("height", &gst::IntRange::<i32>::new(0, i32::MAX)),
+            (
+                "framerate",
+                &gst::FractionRange::new(gst::Fraction::new(0, 1), gst::Fraction::new(i32::MAX, 1)),
+            ),
+        ],
+    )
+}
+
+impl ObjectImpl for S3MultiFrameSink {
+    glib_object_impl!();
+
+    fn set_property(&self, obj: &glib::Object, id: usize, value: &glib::Value) {
+        let prop = &PROPERTIES[id];
+        let element = obj.downcast_ref::<gst_base::BaseSink>().unwrap();
+        let mut settings = self.lock_settings();
+        match *prop {
+            subclass::Property("bucket", ..) => {
+                settings.bucket = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("key", ..) => {
+                settings.key = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("key-template", ..) => {
+                settings.key_template = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("extension", ..) => {
+                settings.extension = value.get::<String>().expect("Type checked upstream")
+            }
+            subclass::Property("endpoint", ..) => {
+                let endpoint = value.get::<String>().expect("Type checked upstream");
+                match endpoint.as_deref().map(region::parse_endpoint) {
+                    Some(Ok(endpoint)) => settings.endpoint = Some(endpoint),
+                    Some(Err(error)) => {
+                        gst_warning!(CAT, obj: element, "Ignoring endpoint: {}", error)
+                    }
+                    None => settings.endpoint = None,
+                }
+            }
+            subclass::Property("access-key", ..) => {
+                settings.access_key = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("secret-key", ..) => {
+                settings.secret_key = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("create-bucket", ..) => {
+                settings.create_bucket = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("async-uploads", ..) => {
+                settings.async_uploads = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("max-in-flight", ..) => {
+                settings.max_in_flight = value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("retry-attempts", ..) => {
+                settings.retry_attempts = value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("retry-jitter-base-ms", ..) => {
+                settings.retry_jitter_base_ms =
+                    value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("retry-jitter-max-ms", ..) => {
+                settings.retry_jitter_max_ms =
+                    value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("region", ..) => {
+                let value = glib::EnumValue::from_value(value).expect("Type checked upstream");
+                settings.region = region::from_enum_value(value.get_value());
+            }
+            subclass::Property("server-side-encryption", ..) => {
+                let server_side_encryption = value.get::<String>().expect("Type checked upstream");
+                match server_side_encryption.as_deref() {
+                    None | Some("AES256") | Some("aws:kms") => {
+                        settings.server_side_encryption = server_side_encryption
+                    }
+                    Some(other) => gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Ignoring unknown server-side encryption {}",
+                        other
+                    ),
+                }
+            }
+            subclass::Property("sse-kms-key-id", ..) => {
+                settings.sse_kms_key_id = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("storage-class", ..) => {
+                let storage_class = value.get::<String>().expect("Type checked upstream");
+                match storage_class.as_deref() {
+                    Some(class) if !STORAGE_CLASSES.contains(&class) => gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Ignoring unknown storage class {}",
+                        class
+                    ),
+                    _ => settings.storage_class = storage_class,
+                }
+            }
+            subclass::Property("tags", ..) => {
+                let tags = value.get::<String>().expect("Type checked upstream");
+                match tags.as_deref() {
+                    Some(tags) if !valid_tags(tags) => {
+                        gst_warning!(CAT, obj: element, "Ignoring malformed tags {}", tags)
+                    }
+                    _ => settings.tags = tags,
+                }
+            }
+            subclass::Property("object-acl", ..) => {
+                let acl = value.get::<String>().expect("Type checked upstream");
+                match acl.as_deref() {
+                    Some(name) if !OBJECT_ACLS.contains(&name) => {
+                        gst_warning!(CAT, obj: element, "Ignoring unknown object ACL {}", name)
+                    }
+                    _ => settings.object_acl = acl,
+                }
+            }
+            subclass::Property("bucket-acl", ..) => {
+                let acl = value.get::<String>().expect("Type checked upstream");
+                match acl.as_deref() {
+                    Some(name) if !BUCKET_ACLS.contains(&name) => {
+                        gst_warning!(CAT, obj: element, "Ignoring unknown bucket ACL {}", name)
+                    }
+                    _ => settings.bucket_acl = acl,
+                }
+            }
+            subclass::Property("verify-integrity", ..) => {
+                settings.verify_integrity =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("write-metadata", ..) => {
+                settings.write_metadata = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("dry-run", ..) => {
+                settings.dry_run = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("profile", ..) => {
+                settings.profile = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("content-disposition", ..) => {
+                settings.content_disposition =
+                    value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("runtime-threads", ..) => {
+                settings.runtime_threads = value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("batch-size", ..) => {
+                settings.batch_size = value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("accept-existing-bucket", ..) => {
+                settings.accept_existing_bucket =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("start-index", ..) => {
+                settings.start_index = value.get_some::<u64>().expect("Type checked upstream");
+            }
+            subclass::Property("write-manifest", ..) => {
+                settings.write_manifest = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("max-uploads-per-second", ..) => {
+                settings.max_uploads_per_second =
+                    value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("auto-region", ..) => {
+                settings.auto_region = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("meta-reference", ..) => {
+                settings.meta_reference = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("request-timeout-ms", ..) => {
+                settings.request_timeout_ms =
+                    value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("delete-on-stop", ..) => {
+                settings.delete_on_stop = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("assume-role-arn", ..) => {
+                settings.assume_role_arn = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("assume-role-external-id", ..) => {
+                settings.assume_role_external_id =
+                    value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("key-separator", ..) => {
+                settings.key_separator = value
+                    .get::<String>()
+                    .expect("Type checked upstream")
+                    .unwrap_or_default();
+            }
+            subclass::Property("allow-empty-frames", ..) => {
+                settings.allow_empty_frames =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("multipart-threshold", ..) => {
+                settings.multipart_threshold =
+                    value.get_some::<u64>().expect("Type checked upstream");
+            }
+            subclass::Property("content-encoding", ..) => {
+                settings.content_encoding = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("max-total-bytes", ..) => {
+                settings.max_total_bytes = value.get_some::<u64>().expect("Type checked upstream");
+            }
+            subclass::Property("no-overwrite", ..) => {
+                settings.no_overwrite = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("key-extension", ..) => {
+                settings.key_extension = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("preflight-check", ..) => {
+                settings.preflight_check = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("compress", ..) => {
+                settings.compress = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("date-partition", ..) => {
+                settings.date_partition = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("date-partition-granularity", ..) => {
+                let value = glib::EnumValue::from_value(value).expect("Type checked upstream");
+                settings.date_partition_granularity =
+                    Granularity::from_enum_value(value.get_value())
+                        .unwrap_or(DEFAULT_DATE_PARTITION_GRANULARITY);
+            }
+            subclass::Property("custom-headers", ..) => {
+                let custom_headers = value.get::<String>().expect("Type checked upstream");
+                match custom_headers
+                    .as_deref()
+                    .map(request_dispatcher::parse_headers)
+                {
+                    Some(Err(error)) => gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Ignoring custom headers: {}",
+                        error
+                    ),
+                    _ => settings.custom_headers = custom_headers,
+                }
+            }
+            subclass::Property("drop-on-backpressure", ..) => {
+                settings.drop_on_backpressure =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("resolution-in-key", ..) => {
+                settings.resolution_in_key =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("request-payer", ..) => {
+                let request_payer = value.get::<String>().expect("Type checked upstream");
+                match request_payer.as_deref() {
+                    None | Some("requester") => settings.request_payer = request_payer,
+                    Some(other) => gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Ignoring unknown request payer {}",
+                        other
+                    ),
+                }
+            }
+            subclass::Property("auto-key", ..) => {
+                settings.auto_key = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("reframe-jpeg", ..) => {
+                settings.reframe_jpeg = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("bucket-grant-read", ..) => {
+                settings.bucket_grant_read = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("bucket-grant-full-control", ..) => {
+                settings.bucket_grant_full_control =
+                    value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("timestamp-source", ..) => {
+                let value = glib::EnumValue::from_value(value).expect("Type checked upstream");
+                settings.timestamp_source = TimestampSource::from_enum_value(value.get_value());
+            }
+            subclass::Property("max-consecutive-failures", ..) => {
+                settings.max_consecutive_failures =
+                    value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("hash-in-key", ..) => {
+                settings.hash_in_key = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("skip-duplicate-frames", ..) => {
+                settings.skip_duplicate_frames =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("cache-control", ..) => {
+                settings.cache_control = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("paused", ..) => {
+                settings.paused = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("paused-queue-size", ..) => {
+                settings.paused_queue_size =
+                    value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("runtime-stack-size", ..) => {
+                settings.runtime_stack_size =
+                    value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("object-lock-mode", ..) => {
+                let mode = value.get::<String>().expect("Type checked upstream");
+                match mode.as_deref().map(ObjectLockMode::from_str) {
+                    Some(Ok(mode)) => settings.object_lock_mode = Some(mode),
+                    Some(Err(error)) => gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Ignoring object lock mode: {}",
+                        error
+                    ),
+                    None => settings.object_lock_mode = None,
+                }
+            }
+            subclass::Property("object-lock-retain-until", ..) => {
+                settings.object_lock_retain_until =
+                    value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("frame-interval", ..) => {
+                settings.frame_interval = value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("filename-digits", ..) => {
+                settings.filename_digits = value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("write-sidecar", ..) => {
+                settings.write_sidecar = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("sidecar-failure-fatal", ..) => {
+                settings.sidecar_failure_fatal =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("key-suffix", ..) => {
+                settings.key_suffix = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("expires", ..) => {
+                let expires = value.get::<String>().expect("Type checked upstream");
+                match expires.as_deref().map(Expires::from_str) {
+                    Some(Ok(expires)) => settings.expires = Some(expires),
+                    Some(Err(error)) => {
+                        gst_warning!(CAT, obj: element, "Ignoring expires: {}", error)
+                    }
+                    None => settings.expires = None,
+                }
+            }
+            subclass::Property("tolerate-create-bucket-errors", ..) => {
+                settings.tolerate_create_bucket_errors =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("reset-on-segment", ..) => {
+                settings.reset_on_segment =
+                    value.get_some::<bool>().expect("Type checked upstream");
+            }
+            subclass::Property("zero-copy-threshold", ..) => {
+                settings.zero_copy_threshold =
+                    value.get_some::<u64>().expect("Type checked upstream");
+            }
+            subclass::Property("throttle-base-ms", ..) => {
+                settings.throttle_base_ms = value.get_some::<u32>().expect("Type checked upstream");
+            }
+            subclass::Property("mirror-bucket", ..) => {
+                settings.mirror_bucket = value.get::<String>().expect("Type checked upstream");
+            }
+            subclass::Property("mirror-region", ..) => {
+                let value = glib::EnumValue::from_value(value).expect("Type checked upstream");
+                settings.mirror_region = Some(region::from_enum_value(value.get_value()));
+            }
+            subclass::Property("mirror-mode", ..) => {
+                let value = glib::EnumValue::from_value(value).expect("Type checked upstream");
+                settings.mirror_mode = MirrorMode::from_enum_value(value.get_value());
+            }
+            subclass::Property("timestamp-format", ..) => {
+                let format = value.get::<String>().expect("Type checked upstream");
+                match format.as_deref().map(TimestampFormat::from_str) {
+                    Some(Ok(format)) => settings.timestamp_format = Some(format),
+                    Some(Err(error)) => gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Ignoring timestamp format: {}",
+                        error
+                    ),
+                    None => settings.timestamp_format = None,
+                }
+            }
+            subclass::Property("resume", ..) => {
+                settings.resume = value.get_some::<bool>().expect("Type checked upstream");
+            }
+            _ => unimplemented!(),
+        };
+    }
+
+    fn get_property(&self, _: &glib::Object, id: usize) -> Result<glib::Value, ()> {
+        let prop = &PROPERTIES[id];
+
+        let settings = self.lock_settings();
+        match *prop {
+            subclass::Property("bucket", ..) => {
+                let bucket = settings
+                    .bucket
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(bucket.to_value())
+            }
+            subclass::Property("key", ..) => {
+                let key = settings.key.as_ref().map(|location| location.to_string());
+                Ok(key.to_value())
+            }
+            subclass::Property("key-template", ..) => {
+                let key_template = settings
+                    .key_template
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(key_template.to_value())
+            }
+            subclass::Property("extension", ..) => {
+                let extension = settings
+                    .extension
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(extension.to_value())
+            }
+            subclass::Property("region", ..) => {
+                let class = glib::EnumClass::new(region::region_type()).unwrap();
+                Ok(class
+                    .to_value(region::to_enum_value(&settings.region))
+                    .unwrap())
+            }
+            subclass::Property("endpoint", ..) => {
+                let endpoint = settings
+                    .endpoint
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(endpoint.to_value())
+            }
+            subclass::Property("access-key", ..) => {
+                let access_key = settings
+                    .access_key
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(access_key.to_value())
+            }
+            subclass::Property("secret-key", ..) => {
+                let secret_key = settings
+                    .secret_key
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(secret_key.to_value())
+            }
+            subclass::Property("create-bucket", ..) => Ok(settings.create_bucket.to_value()),
+            subclass::Property("server-side-encryption", ..) => {
+                let server_side_encryption = settings
+                    .server_side_encryption
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(server_side_encryption.to_value())
+            }
+            subclass::Property("sse-kms-key-id", ..) => {
+                let sse_kms_key_id = settings
+                    .sse_kms_key_id
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(sse_kms_key_id.to_value())
+            }
+            subclass::Property("storage-class", ..) => {
+                let storage_class = settings
+                    .storage_class
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(storage_class.to_value())
+            }
+            subclass::Property("tags", ..) => {
+                let tags = settings.tags.as_ref().map(|location| location.to_string());
+                Ok(tags.to_value())
+            }
+            subclass::Property("object-acl", ..) => {
+                let object_acl = settings
+                    .object_acl
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(object_acl.to_value())
+            }
+            subclass::Property("bucket-acl", ..) => {
+                let bucket_acl = settings
+                    .bucket_acl
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(bucket_acl.to_value())
+            }
+            subclass::Property("verify-integrity", ..) => Ok(settings.verify_integrity.to_value()),
+            subclass::Property("write-metadata", ..) => Ok(settings.write_metadata.to_value()),
+            subclass::Property("dry-run", ..) => Ok(settings.dry_run.to_value()),
+            subclass::Property("profile", ..) => {
+                let profile = settings
+                    .profile
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(profile.to_value())
+            }
+            subclass::Property("content-disposition", ..) => {
+                let content_disposition = settings
+                    .content_disposition
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(content_disposition.to_value())
+            }
+            subclass::Property("runtime-threads", ..) => Ok(settings.runtime_threads.to_value()),
+            subclass::Property("batch-size", ..) => Ok(settings.batch_size.to_value()),
+            subclass::Property("accept-existing-bucket", ..) => {
+                Ok(settings.accept_existing_bucket.to_value())
+            }
+            subclass::Property("start-index", ..) => Ok(settings.start_index.to_value()),
+            subclass::Property("write-manifest", ..) => Ok(settings.write_manifest.to_value()),
+            subclass::Property("max-uploads-per-second", ..) => {
+                Ok(settings.max_uploads_per_second.to_value())
+            }
+            subclass::Property("auto-region", ..) => Ok(settings.auto_region.to_value()),
+            subclass::Property("meta-reference", ..) => {
+                let meta_reference = settings
+                    .meta_reference
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(meta_reference.to_value())
+            }
+            subclass::Property("request-timeout-ms", ..) => {
+                Ok(settings.request_timeout_ms.to_value())
+            }
+            subclass::Property("delete-on-stop", ..) => Ok(settings.delete_on_stop.to_value()),
+            subclass::Property("assume-role-arn", ..) => {
+                let assume_role_arn = settings
+                    .assume_role_arn
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(assume_role_arn.to_value())
+            }
+            subclass::Property("assume-role-external-id", ..) => {
+                let assume_role_external_id = settings
+                    .assume_role_external_id
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(assume_role_external_id.to_value())
+            }
+            subclass::Property("key-separator", ..) => Ok(settings.key_separator.to_value()),
+            subclass::Property("allow-empty-frames", ..) => {
+                Ok(settings.allow_empty_frames.to_value())
+            }
+            subclass::Property("multipart-threshold", ..) => {
+                Ok(settings.multipart_threshold.to_value())
+            }
+            subclass::Property("content-encoding", ..) => {
+                let content_encoding = settings
+                    .content_encoding
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(content_encoding.to_value())
+            }
+            subclass::Property("max-total-bytes", ..) => Ok(settings.max_total_bytes.to_value()),
+            subclass::Property("no-overwrite", ..) => Ok(settings.no_overwrite.to_value()),
+            subclass::Property("key-extension", ..) => {
+                let key_extension = settings
+                    .key_extension
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(key_extension.to_value())
+            }
+            subclass::Property("preflight-check", ..) => Ok(settings.preflight_check.to_value()),
+            subclass::Property("compress", ..) => Ok(settings.compress.to_value()),
+            subclass::Property("date-partition", ..) => Ok(settings.date_partition.to_value()),
+            subclass::Property("date-partition-granularity", ..) => {
+                let class = glib::EnumClass::new(date_partition::granularity_type()).unwrap();
+                Ok(class
+                    .to_value(settings.date_partition_granularity.to_enum_value())
+                    .unwrap())
+            }
+            subclass::Property("custom-headers", ..) => {
+                let custom_headers = settings
+                    .custom_headers
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(custom_headers.to_value())
+            }
+            subclass::Property("drop-on-backpressure", ..) => {
+                Ok(settings.drop_on_backpressure.to_value())
+            }
+            subclass::Property("resolution-in-key", ..) => {
+                Ok(settings.resolution_in_key.to_value())
+            }
+            subclass::Property("request-payer", ..) => {
+                let request_payer = settings
+                    .request_payer
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(request_payer.to_value())
+            }
+            subclass::Property("auto-key", ..) => Ok(settings.auto_key.to_value()),
+            subclass::Property("reframe-jpeg", ..) => Ok(settings.reframe_jpeg.to_value()),
+            subclass::Property("bucket-grant-read", ..) => {
+                let bucket_grant_read = settings
+                    .bucket_grant_read
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(bucket_grant_read.to_value())
+            }
+            subclass::Property("bucket-grant-full-control", ..) => {
+                let bucket_grant_full_control = settings
+                    .bucket_grant_full_control
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(bucket_grant_full_control.to_value())
+            }
+            subclass::Property("timestamp-source", ..) => {
+                let class = glib::EnumClass::new(*TIMESTAMP_SOURCE_TYPE).unwrap();
+                Ok(class
+                    .to_value(settings.timestamp_source.to_enum_value())
+                    .unwrap())
+            }
+            subclass::Property("max-consecutive-failures", ..) => {
+                Ok(settings.max_consecutive_failures.to_value())
+            }
+            subclass::Property("hash-in-key", ..) => Ok(settings.hash_in_key.to_value()),
+            subclass::Property("skip-duplicate-frames", ..) => {
+                Ok(settings.skip_duplicate_frames.to_value())
+            }
+            subclass::Property("cache-control", ..) => {
+                let cache_control = settings
+                    .cache_control
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(cache_control.to_value())
+            }
+            subclass::Property("paused", ..) => Ok(settings.paused.to_value()),
+            subclass::Property("paused-queue-size", ..) => {
+                Ok(settings.paused_queue_size.to_value())
+            }
+            subclass::Property("runtime-stack-size", ..) => {
+                Ok(settings.runtime_stack_size.to_value())
+            }
+            subclass::Property("object-lock-mode", ..) => {
+                let mode = settings.object_lock_mode.map(ObjectLockMode::name);
+                Ok(mode.to_value())
+            }
+            subclass::Property("object-lock-retain-until", ..) => {
+                let object_lock_retain_until = settings
+                    .object_lock_retain_until
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(object_lock_retain_until.to_value())
+            }
+            subclass::Property("frame-interval", ..) => Ok(settings.frame_interval.to_value()),
+            subclass::Property("filename-digits", ..) => Ok(settings.filename_digits.to_value()),
+            subclass::Property("write-sidecar", ..) => Ok(settings.write_sidecar.to_value()),
+            subclass::Property("sidecar-failure-fatal", ..) => {
+                Ok(settings.sidecar_failure_fatal.to_value())
+            }
+            subclass::Property("key-suffix", ..) => {
+                let key_suffix = settings
+                    .key_suffix
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(key_suffix.to_value())
+            }
+            subclass::Property("expires", ..) => {
+                let expires = settings.expires.as_ref().map(Expires::to_string);
+                Ok(expires.to_value())
+            }
+            subclass::Property("tolerate-create-bucket-errors", ..) => {
+                Ok(settings.tolerate_create_bucket_errors.to_value())
+            }
+            subclass::Property("reset-on-segment", ..) => Ok(settings.reset_on_segment.to_value()),
+            subclass::Property("zero-copy-threshold", ..) => {
+                Ok(settings.zero_copy_threshold.to_value())
+            }
+            subclass::Property("throttle-base-ms", ..) => Ok(settings.throttle_base_ms.to_value()),
+            subclass::Property("mirror-bucket", ..) => {
+                let mirror_bucket = settings
+                    .mirror_bucket
+                    .as_ref()
+                    .map(|location| location.to_string());
+                Ok(mirror_bucket.to_value())
+            }
+            subclass::Property("mirror-region", ..) => {
+                // Unless set, the mirror is in the same region as the bucket
+                let mirror_region = settings.mirror_region.as_ref().unwrap_or(&settings.region);
+                let class = glib::EnumClass::new(region::region_type()).unwrap();
+                Ok(class
+                    .to_value(region::to_enum_value(mirror_region))
+                    .unwrap())
+            }
+            subclass::Property("mirror-mode", ..) => {
+                let class = glib::EnumClass::new(*MIRROR_MODE_TYPE).unwrap();
+                Ok(class
+                    .to_value(settings.mirror_mode.to_enum_value())
+                    .unwrap())
+            }
+            subclass::Property("timestamp-format", ..) => {
+                let format = settings
+                    .timestamp_format
+                    .as_ref()
+                    .map(|format| format.pattern().to_string());
+                Ok(format.to_value())
+            }
+            subclass::Property("resume", ..) => Ok(settings.resume.to_value()),
+            subclass::Property("frames-uploaded", ..) => {
+                Ok(self.lock_stats().frames_uploaded.to_value())
+            }
+            subclass::Property("avg-upload-ms", ..) => {
+                let stats = self.lock_stats();
+                let avg_upload_ms = if stats.upload_times.is_empty() {
+                    0
+                } else {
+                    let total: Duration = stats.upload_times.iter().sum();
+                    total.as_millis() as u64 / stats.upload_times.len() as u64
+                };
+                Ok(avg_upload_ms.to_value())
+            }
+            subclass::Property("last-key", ..) => Ok(self.lock_stats().last_key.to_value()),
+            subclass::Property("frames-dropped", ..) => {
+                Ok(self.lock_stats().frames_dropped.to_value())
+            }
+            subclass::Property("async-uploads", ..) => Ok(settings.async_uploads.to_value()),
+            subclass::Property("max-in-flight", ..) => Ok(settings.max_in_flight.to_value()),
+            subclass::Property("retry-attempts", ..) => Ok(settings.retry_attempts.to_value()),
+            subclass::Property("retry-jitter-base-ms", ..) => {
+                Ok(settings.retry_jitter_base_ms.to_value())
+            }
+            subclass::Property("retry-jitter-max-ms", ..) => {
+                Ok(settings.retry_jitter_max_ms.to_value())
+            }
+            _ => unimplemented!(),
+        }
+    }
+}
+
+impl ElementImpl for S3MultiFrameSink {}
+
+impl BaseSinkImpl for S3MultiFrameSink {
+    fn start(&self, element: &gst_base::BaseSink) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.lock_state();
+        if let State::Started(_) = *state {
+            unreachable!("S3MultiFrameSink already started");
+        }
+
+        let mut settings = self.lock_settings();
+        if settings.bucket.is_none() {
+            return Err(gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["No bucket specified"]
+            ));
+        }
+        if settings.key.is_none() {
+            if !settings.auto_key {
+                return Err(gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["No key specified"]
+                ));
+            }
+            // Regenerated on every start, so each run gets its own prefix
+            let timestamp_ms = SystemTime::now()
+                .duration_since(UNIX_EPOCH)
+                .map(|elapsed| elapsed.as_millis())
+                .unwrap_or_default();
+            let generated_key = format!("capture-{}", settings.render_timestamp(timestamp_ms));
+            gst_info!(CAT, obj: element, "Using generated key {}", generated_key);
+            settings.generated_key = Some(generated_key);
+        }
+        if matches!(settings.endpoint, Some(ref endpoint) if endpoint.starts_with("http://")) {
+            gst_info!(
+                CAT,
+                obj: element,
+                "Endpoint {} is plain HTTP, requests are not encrypted",
+                settings.endpoint.as_deref().unwrap_or_default()
+            );
+        }
+        if settings.region == region::custom() && settings.endpoint.is_none() {
+            return Err(gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["The custom region needs an endpoint"]
+            ));
+        }
+        if settings.mirror_region == Some(region::custom()) && settings.endpoint.is_none() {
+            return Err(gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["The custom mirror region needs an endpoint"]
+            ));
+        }
+        if settings.object_lock_mode.is_some() != settings.object_lock_retain_until.is_some() {
+            return Err(gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["object-lock-mode and object-lock-retain-until must be set together"]
+            ));
+        }
+        if settings.profile.is_some()
+            && settings.access_key.is_some()
+            && settings.secret_key.is_some()
+        {
+            gst_warning!(
+                CAT,
+                obj: element,
+                "Both a profile and static keys are set, ignoring the profile"
+            );
+        }
+        let s3client = if settings.dry_run {
+            None
+        } else {
+            Some(S3MultiFrameSink::create_s3client(&settings)?)
+        };
+        // The mirror client is made once the bucket's region is known
+        let mirror_settings = match (&s3client, &settings.mirror_bucket) {
+            (Some(_), Some(_)) => Some(settings.clone()),
+            _ => None,
+        };
+        let key_template = KeyTemplate::from_str(
+            &settings
+                .key_template
+                .clone()
+                .unwrap_or_else(|| settings.default_key_template()),
+        )
+        .map_err(|error| {
+            gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["Invalid key template: {}", error]
+            )
+        })?;
+        let content_disposition = settings
+            .content_disposition
+            .as_deref()
+            .map(KeyTemplate::from_str)
+            .transpose()
+            .map_err(|error| {
+                gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Invalid content disposition: {}", error]
+                )
+            })?;
+        let create_bucket = settings.create_bucket;
+        let object_lock = settings.object_lock_mode.is_some();
+        let preflight_check = settings.preflight_check;
+        let auto_region = settings.auto_region;
+        let configured_region = settings.region.clone();
+        let max_in_flight = settings.max_in_flight as usize;
+        let runtime_threads = settings.runtime_threads as usize;
+        let runtime_stack_size = settings.runtime_stack_size as usize;
+        let batch_size = u64::from(settings.batch_size);
+        let start_index = settings.start_index;
+        let resume_prefix = if settings.resume {
+            Some(settings.object_key(""))
+        } else {
+            None
+        };
+        let max_consecutive_failures = settings.max_consecutive_failures;
+        let sidecar_failure_fatal = settings.sidecar_failure_fatal;
+        let rate_limiter = match settings.max_uploads_per_second {
+            0 => None,
+            per_second => Some(RateLimiter::new(per_second)),
+        };
+        let jpeg_reframer = if settings.reframe_jpeg {
+            Some(JpegReframer::default())
+        } else {
+            None
+        };
+        let manifest = if settings.write_manifest {
+            Some(Vec::new())
+        } else {
+            None
+        };
+        drop(settings);
+        let mut builder = runtime::Builder::new();
+        builder
+            .threaded_scheduler()
+            .enable_all()
+            // Named after the element, so threads are told apart when several sinks run
+            .thread_name(format!("s3sink-{}", element.get_name()));
+        if runtime_threads > 0 {
+            builder.core_threads(runtime_threads);
+        }
+        if runtime_stack_size > 0 {
+            builder.thread_stack_size(runtime_stack_size);
+        }
+        let runtime = builder.build().map_err(|error| {
+            gst_error_msg!(
+                gst::ResourceError::Failed,
+                ["Failed to create runtime: {}", error]
+            )
+        })?;
+        let (s3client, region) = match s3client {
+            Some(s3client) if auto_region => {
+                let (s3client, region) = self.detect_region(element, &runtime, s3client)?;
+                (Some(s3client), region)
+            }
+            s3client => (s3client, configured_region),
+        };
+        // The mirror shares the credentials and endpoint, only the region differs.
+        // Without mirror-region it follows the bucket.
+        let mirror_s3client = match mirror_settings {
+            Some(mut mirror_settings) => {
+                mirror_settings.region = mirror_settings
+                    .mirror_region
+                    .clone()
+                    .unwrap_or_else(|| region.clone());
+                Some(S3MultiFrameSink::create_s3client(&mirror_settings)?)
+            }
+            None => None,
+        };
+        // Creating the bucket checks it first, so it doubles as the preflight check
+        let bucket_created = match (create_bucket, &s3client) {
+            (true, Some(s3client)) => self.create_bucket_if_extant(element, &runtime, s3client)?,
+            (false, Some(s3client)) if preflight_check => {
+                self.check_bucket(element, &runtime, s3client)?;
+                false
+            }
+            _ => false,
+        };
+        if let (true, Some(s3client)) = (object_lock, &s3client) {
+            self.check_object_lock(element, &runtime, s3client);
+        }
+
+        let frame_num = match (resume_prefix, &s3client) {
+            (Some(resume_prefix), Some(s3client)) => {
+                match self.last_frame_num(element, &runtime, s3client, resume_prefix)? {
+                    Some(last) if last >= start_index => {
+                        gst_info!(CAT, obj: element, "Resuming after frame {}", last);
+                        last + 1
+                    }
+                    _ => start_index,
+                }
+            }
+            _ => start_index,
+        };
+
+        let bucket = if bucket_created {
+            self.lock_settings().bucket.clone()
+        } else {
+            None
+        };
+        let uploads = UploadQueue::new(runtime.handle().clone(), max_in_flight);
+        *self.lock_stats() = Stats {
+            upload_times: VecDeque::with_capacity(UPLOAD_TIMES_WINDOW),
+            ..Default::default()
+        };
+        *state = State::Started(Started {
+            frame_num,
+            runtime,
+            s3client,
+            mirror_s3client,
+            key_template,
+            content_disposition,
+            media_type: None,
+            caps: None,
+            video_info: None,
+            dimensions: None,
+            uploads,
+            batch_size,
+            batch: Default::default(),
+            rate_limiter,
+            manifest,
+            region,
+            bucket_created,
+            bytes_uploaded: 0,
+            uploads_failed: 0,
+            consecutive_failures: 0,
+            max_consecutive_failures,
+            sidecar_failure_fatal,
+            started_at: Instant::now(),
+            last_frame_hash: None,
+            jpeg_reframer,
+            paused_frames: VecDeque::new(),
+            frames_seen: 0,
+            segment_index: 0,
+            clock_anchor: None,
+        });
+        gst_info!(CAT, obj: element, "Started");
+        // Released before emitting so handlers may query the element
+        drop(state);
+        if let Some(bucket) = bucket {
+            gst_info!(CAT, obj: element, "Created bucket {}", bucket);
+            element.emit("bucket-created", &[&bucket]).unwrap();
+        }
+
+        Ok(())
+    }
+
+    fn stop(&self, element: &gst_base::BaseSink) -> Result<(), gst::ErrorMessage> {
+        let mut state = self.lock_state();
+        let started = match *state {
+            State::Started(ref mut started) => started,
+            State::Stopped => {
+                return Err(gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["S3MultiFrameSink not started"]
+                ));
+            }
+        };
+
+        // stop() follows unlock_stop(), so nothing interrupts the wait here
+        let (uploaded, failure, _) = self.finish_uploads(element, started);
+        // Only index the capture once every frame in it made it to the bucket
+        let manifest_result = match failure {
+            None => self.upload_manifest(element, started),
+            Some(_) => Ok(()),
+        };
+        // Never delete a bucket the element didn't create
+        let cleanup_result = if started.bucket_created && self.lock_settings().delete_on_stop {
+            self.delete_created_bucket(element, started)
+        } else {
+            Ok(())
+        };
+        // Reset so the stats properties read as zero until the next start
+        let counters = std::mem::take(&mut *self.lock_stats());
+        let stats = gst::Structure::builder("s3multiframesink-stats")
+            .field("frames-uploaded", &counters.frames_uploaded)
+            .field("bytes-uploaded", &started.bytes_uploaded)
+            .field("uploads-failed", &started.uploads_failed)
+            .field("frames-dropped", &counters.frames_dropped)
+            .field(
+                "duration-ms",
+                &(started.started_at.elapsed().as_millis() as u64),
+            )
+            .build();
+        let region = started.region.name().to_string();
+        // Dropping the started state shuts down its runtime
+        *state = State::Stopped;
+        drop(state);
+        S3MultiFrameSink::emit_frames_uploaded(element, uploaded);
+        // Posted even when stopping fails, so applications can always log a summary
+        let _ = element.post_message(
+            &gst::Message::new_application(stats)
+                .src(Some(element))
+                .build(),
+        );
+        if let Some((frame_num, error)) = failure {
+            return Err(gst_error_msg!(
+                gst::ResourceError::Write,
+                ["Failed to upload frame {}: {}", frame_num, error]
+            ));
+        }
+        manifest_result?;
+        cleanup_result?;
+        gst_info!(CAT, obj: element, "Stopped uploading to {}", region);
+
+        Ok(())
+    }
+
+    fn unlock(&self, element: &gst_base::BaseSink) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Unlocking");
+        self.canceller.unlock();
+        Ok(())
+    }
+
+    fn unlock_stop(&self, element: &gst_base::BaseSink) -> Result<(), gst::ErrorMessage> {
+        gst_debug!(CAT, obj: element, "Unlock stopped");
+        self.canceller.unlock_stop();
+        Ok(())
+    }
+
+    fn event(&self, element: &gst_base::BaseSink, event: gst::Event) -> bool {
+        if let gst::EventView::Segment(_) = event.view() {
+            if self.lock_settings().reset_on_segment && !self.start_segment(element) {
+                return false;
+            }
+        }
+        if let gst::EventView::Eos(_) = event.view() {
+            // Hold back EOS until every frame is in the bucket, so applications
+            // can tear down the pipeline as soon as they see it
+            let mut state = self.lock_state();
+            if let State::Started(ref mut started) = *state {
+                let (uploaded, failure, finished) = self.finish_uploads(element, started);
+                drop(state);
+                S3MultiFrameSink::emit_frames_uploaded(element, uploaded);
+                // A flush interrupted the wait, so the EOS is dropped with it
+                if let Err(Unlocked) = finished {
+                    gst_debug!(CAT, obj: element, "Waiting for uploads interrupted by unlock");
+                    return false;
+                }
+                if let Some((frame_num, error)) = failure {
+                    gst_element_error!(
+                        element,
+                        gst::ResourceError::Write,
+                        ["Failed to upload frame {}: {}", frame_num, error]
+                    );
+                    return false;
+                }
+            }
+        }
+        self.parent_event(element, event)
+    }
+
+    fn set_caps(
+        &self,
+        element: &gst_base::BaseSink,
+        caps: &gst::Caps,
+    ) -> Result<(), gst::LoggableError> {
+        let structure = caps
+            .get_structure(0)
+            .ok_or_else(|| gst_loggable_error!(CAT, "Caps without a structure: {}", caps))?;
+        let name = structure.get_name();
+        if media_type_extension(name).is_none() {
+            return Err(gst_loggable_error!(CAT, "Unsupported media type {}", name));
+        }
+
+        let width = structure.get_some::<i32>("width").unwrap_or(0);
+        let height = structure.get_some::<i32>("height").unwrap_or(0);
+        let dimensions = if width > 0 && height > 0 {
+            Some((width as u32, height as u32))
+        } else {
+            None
+        };
+        if dimensions.is_none() && self.lock_settings().resolution_in_key {
+            return Err(gst_loggable_error!(
+                CAT,
+                "resolution-in-key needs caps with dimensions: {}",
+                caps
+            ));
+        }
+
+        let info = if name == "video/x-raw" {
+            Some(
+                gst_video::VideoInfo::from_caps(caps)
+                    .map_err(|_| gst_loggable_error!(CAT, "Failed to parse caps {}", caps))?,
+            )
+        } else {
+            None
+        };
+
+        let mut state = self.lock_state();
+        match *state {
+            State::Started(ref mut started) => {
+                started.media_type = Some(name.to_string());
+                started.caps = Some(caps.to_string());
+                started.video_info = info;
+                started.dimensions = dimensions;
+            }
+            State::Stopped => {
+                return Err(gst_loggable_error!(CAT, "Caps received before start"));
+            }
+        }
+        match dimensions {
+            Some((width, height)) => gst_debug!(
+                CAT,
+                obj: element,
+                "Negotiated media type {} at {}x{}",
+                name,
+                width,
+                height
+            ),
+            None => gst_debug!(CAT, obj: element, "Negotiated media type {}", name),
+        }
+
+        Ok(())
+    }
+
+    fn render(
+        &self,
+        element: &gst_base::BaseSink,
+        buffer: &gst::Buffer,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        self.render_buffers(element, &[buffer.as_ref()])
+    }
+
+    fn render_list(
+        &self,
+        element: &gst_base::BaseSink,
+        list: &gst::BufferList,
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let buffers: Vec<&gst::BufferRef> = list.iter().collect();
+        self.render_buffers(element, &buffers)
+    }
+}
+
+// What {timestamp} and date partitions are derived from
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum TimestampSource {
+    Pts,
+    Wallclock,
+    RunningTime,
+}
+
+// In the order of the enum values
+const TIMESTAMP_SOURCES: [TimestampSource; 3] = [
+    TimestampSource::Pts,
+    TimestampSource::Wallclock,
+    TimestampSource::RunningTime,
+];
+
+// The timestamp-source property is a GEnum, so gst-inspect lists the sources
+static TIMESTAMP_SOURCE_TYPE: Lazy<glib::Type> = Lazy::new(|| {
+    let names: Vec<&str> = TIMESTAMP_SOURCES
+        .iter()
+        .map(|source| source.name())
+        .collect();
+    enum_type::register("GstS3MultiFrameSinkTimestampSource", &names)
+});
+
+impl TimestampSource {
+    fn name(self) -> &'static str {
+        match self {
+            TimestampSource::Pts => "pts",
+            TimestampSource::Wallclock => "wallclock",
+            TimestampSource::RunningTime => "running-time",
+        }
+    }
+
+    fn to_enum_value(self) -> i32 {
+        TIMESTAMP_SOURCES
+            .iter()
+            .position(|&source| source == self)
+            .expect("Every source is listed") as i32
+    }
+
+    fn from_enum_value(value: i32) -> Self {
+        TIMESTAMP_SOURCES
+            .get(value as usize)
+            .copied()
+            .unwrap_or(DEFAULT_TIMESTAMP_SOURCE)
+    }
+}
+
+// The wall clock at the first frame timed by a PTS or running time. Later
+// frames are dated by how far their timestamp is from that frame's.
+#[derive(Debug, Clone, Copy)]
+struct ClockAnchor {
+    source: TimestampSource,
+    ns: u64,
+    wallclock_ms: u128,
+}
+
+impl ClockAnchor {
+    fn wallclock_ms(&self, ns: u64) -> u128 {
+        let offset_ms = (i128::from(ns) - i128::from(self.ns)) / 1_000_000;
+        (self.wallclock_ms as i128 + offset_ms).max(0) as u128
+    }
+}
+
+// The retention mode of object-lock-mode, named as S3 spells it
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum ObjectLockMode {
+    Governance,
+    Compliance,
+}
+
+impl ObjectLockMode {
+    fn name(self) -> &'static str {
+        match self {
+            ObjectLockMode::Governance => "GOVERNANCE",
+            ObjectLockMode::Compliance => "COMPLIANCE",
+        }
+    }
+}
+
+impl FromStr for ObjectLockMode {
+    type Err = String;
+
+    fn from_str(name: &str) -> Result<Self, Self::Err> {
+        match name {
+            "GOVERNANCE" => Ok(ObjectLockMode::Governance),
+            "COMPLIANCE" => Ok(ObjectLockMode::Compliance),
+            _ => Err(format!("unknown object lock mode {}", name)),
+        }
+    }
+}
+
+// The expires property, either fixed or relative to each upload
+#[derive(Debug, Clone, PartialEq)]
+enum Expires {
+    At(String),
+    AfterSeconds(u64),
+}
+
+impl Expires {
+    fn header(&self) -> String {
+        match *self {
+            Expires::At(ref date) => date.clone(),
+            Expires::AfterSeconds(seconds) => {
+                let expires = SystemTime::now() + Duration::from_secs(seconds);
+                let timestamp_ms = expires
+                    .duration_since(UNIX_EPOCH)
+                    .map(|elapsed| elapsed.as_millis())
+                    .unwrap_or_default();
+                date_partition::http_date(timestamp_ms)
+            }
+        }
+    }
+}
+
+impl fmt::Display for Expires {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Expires::At(ref date) => f.write_str(date),
+            Expires::AfterSeconds(seconds) => write!(f, "{}", seconds),
+        }
+    }
+}
+
+impl FromStr for Expires {
+    type Err = String;
+
+    fn from_str(expires: &str) -> Result<Self, Self::Err> {
+        if let Ok(seconds) = expires.parse() {
+            Ok(Expires::AfterSeconds(seconds))
+        } else if date_partition::is_http_date(expires) {
+            Ok(Expires::At(expires.to_string()))
+        } else {
+            Err(format!(
+                "{} is neither an RFC 1123 date nor a number of seconds",
+                expires
+            ))
+        }
+    }
+}
+
+// Which buckets an upload must reach when mirroring
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum MirrorMode {
+    All,
+    Any,
+}
+
+// In the order of the enum values
+const MIRROR_MODES: [MirrorMode; 2] = [MirrorMode::All, MirrorMode::Any];
+
+static MIRROR_MODE_TYPE: Lazy<glib::Type> = Lazy::new(|| {
+    let names: Vec<&str> = MIRROR_MODES.iter().map(|mode| mode.name()).collect();
+    enum_type::register("GstS3MultiFrameSinkMirrorMode", &names)
+});
+
+impl MirrorMode {
+    fn name(self) -> &'static str {
+        match self {
+            MirrorMode::All => "all",
+            MirrorMode::Any => "any",
+        }
+    }
+
+    fn to_enum_value(self) -> i32 {
+        MIRROR_MODES
+            .iter()
+            .position(|&mode| mode == self)
+            .expect("Every mode is listed") as i32
+    }
+
+    fn from_enum_value(value: i32) -> Self {
+        MIRROR_MODES
+            .get(value as usize)
+            .copied()
+            .unwrap_or(DEFAULT_MIRROR_MODE)
+    }
+
+    // Combines the results of the same request to both buckets
+    fn combine<E>(self, primary: Result<(), E>, mirror: Result<(), E>) -> Result<(), E> {
+        match self {
+            MirrorMode::All => primary.and(mirror),
+            // Reports the primary's error when both fail
+            MirrorMode::Any => mirror.or(primary),
+        }
+    }
+}
+
+// The timestamps of a buffer, in nanoseconds
+#[derive(Debug, Clone, Copy)]
+struct FrameTimes {
+    pts: Option<u64>,
+    running_time: Option<u64>,
+    // From the reference timestamp meta, for the {meta} token
+    meta: Option<u64>,
+}
+
+// Frames accumulated for the next archive upload when batching
+#[derive(Debug, Default)]
+struct Batch {
+    index: u64,
+    last_frame: Option<(u64, Option<u64>)>,
+    archive: TarArchive,
+    entries: Vec<ManifestEntry>,
+}
+
+// A frame as listed in manifest.json, under the key of the object it ends up in
+#[derive(Debug, Clone)]
+struct ManifestEntry {
+    frame_num: u64,
+    pts: Option<u64>,
+    size: usize,
+}
+
+// Tags are `key=value` pairs joined by `&`, with keys and values URL-encoded
+fn valid_tags(tags: &str) -> bool {
+    tags.split('&').all(|pair| {
+        let mut parts = pair.splitn(2, '=');
+        match (parts.next(), parts.next()) {
+            (Some(key), Some(value)) => !key.is_empty() && !value.contains('='),
+            _ => false,
+        }
+    })
+}
+
+fn media_type_extension(media_type: &str) -> Option<&'static str> {
+    match media_type {
+        "image/jpeg" => Some("jpg"),
+        "image/png" => Some("png"),
+        "image/tiff" => Some("tif"),
+        "image/gif" => Some("gif"),
+        "image/webp" => Some("webp"),
+        "image/bmp" => Some("bmp"),
+        "video/x-raw" => Some("raw"),
+        _ => None,
+    }
+}
+
+fn media_type_content_type(media_type: &str) -> &str {
+    match media_type {
+        "video/x-raw" => "application/octet-stream",
+        image => image,
+    }
+}
+
+// The frame number of an object named by the default key template, relative
+// to the key prefix. Date partitions and other folders before the name are
+// skipped, whether joined by `/` or by the key separator.
+fn key_frame_num(name: &str, key_separator: &str) -> Option<u64> {
+    let name = name.rsplit('/').next().unwrap_or_default();
+    let mut starts = vec![0];
+    if !key_separator.is_empty() {
+        starts.extend(
+            name.match_indices(key_separator)
+                .map(|(index, _)| index + key_separator.len()),
+        );
+    }
+    starts.into_iter().rev().find_map(|start| {
+        let digits = name[start..].strip_prefix("frame")?;
+        let len = digits
+            .find(|c: char| !c.is_ascii_digit())
+            .unwrap_or(digits.len());
+        digits[..len].parse().ok()
+    })
+}
+
+// The first 8 hex digits of the SHA-256 of the data, for content-addressed keys
+fn short_hash(data: &[u8]) -> String {
+    Sha256::digest(data)
+        .iter()
+        .take(4)
+        .fold(String::new(), |mut hash, byte| {
+            write!(hash, "{:02x}", byte).unwrap();
+            hash
+        })
+}
+
+fn gzip(data: &[u8]) -> Vec<u8> {
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::default());
+    encoder
+        .write_all(data)
+        .expect("Writing to a Vec never fails");
+    encoder.finish().expect("Writing to a Vec never fails")
+}
+
+struct UploadResult {
+    frame_num: u64,
+    key: String,
+    // The number of frames in the object, more than one when batching
+    frames: u64,
+    size: usize,
+    elapsed: Duration,
+    entries: Vec<ManifestEntry>,
+    result: Result<(), RusotoError<PutObjectError>>,
+    // Only attempted once the frame itself is uploaded
+    sidecar_result: Option<Result<(), RusotoError<PutObjectError>>>,
+}
+type PutResult = Result<(), RusotoError<PutObjectError>>;
+type UploadFailure = (u64, RusotoError<PutObjectError>);
+// The uploaded frames and first failure, and whether the wait was interrupted
+type FinishedUploads = (
+    Vec<(u64, String)>,
+    Option<UploadFailure>,
+    Result<(), Unlocked>,
+);
+
+#[derive(Clone)]
+struct FrameUpload {
+    frame_num: u64,
+    pts: Option<u64>,
+    key: String,
+    content_type: String,
+    content_disposition: Option<String>,
+    // Shared rather than copied by each request attempt and multipart part
+    body: FrameBody,
+    frames: u64,
+    // When the frame was rendered, for archive entry times
+    wallclock_ms: u128,
+    // The frame's {timestamp}, for write-metadata
+    timestamp: Option<String>,
+    // The key and body of the frame's write-sidecar JSON
+    sidecar: Option<(String, Vec<u8>)>,
+    // The frames in the object, for the manifest
+    entries: Vec<ManifestEntry>,
+    settings: Settings,
+}
+
+impl FrameUpload {
+    async fn upload(
+        self,
+        s3client: S3Client,
+        mirror_s3client: Option<S3Client>,
+        element: gst::Element,
+    ) -> UploadResult {
+        let started_at = Instant::now();
+        let (result, sidecar_result) = match mirror_s3client {
+            Some(mirror_s3client) => {
+                self.upload_mirrored(&s3client, &mirror_s3client, &element)
+                    .await
+            }
+            None => self.upload_to(&s3client, &element).await,
+        };
+        UploadResult {
+            frame_num: self.frame_num,
+            key: self.key,
+            frames: self.frames,
+            size: self.body.len(),
+            elapsed: started_at.elapsed(),
+            entries: self.entries,
+            result,
+            sidecar_result,
+        }
+    }
+
+    // Uploads to mirror-bucket alongside the bucket, succeeding as mirror-mode asks
+    async fn upload_mirrored(
+        &self,
+        s3client: &S3Client,
+        mirror_s3client: &S3Client,
+        element: &gst::Element,
+    ) -> (PutResult, Option<PutResult>) {
+        let mut mirror = self.clone();
+        mirror.settings.bucket = self.settings.mirror_bucket.clone();
+        let ((result, sidecar_result), (mirror_result, mirror_sidecar_result)) = future::join(
+            self.upload_to(s3client, element),
+            mirror.upload_to(mirror_s3client, element),
+        )
+        .await;
+        let mode = self.settings.mirror_mode;
+        if mode == MirrorMode::Any && result.is_ok() != mirror_result.is_ok() {
+            let (bucket, error) = match (&result, &mirror_result) {
+                (Err(error), _) => (&self.settings.bucket, error),
+                (_, Err(error)) => (&mirror.settings.bucket, error),
+                _ => unreachable!(),
+            };
+            gst_warning!(
+                CAT,
+                obj: element,
+                "Frame {} only reached one bucket, failing for {}: {}",
+                self.frame_num,
+                bucket.as_deref().unwrap_or_default(),
+                error
+            );
+        }
+        let sidecar_result = match (sidecar_result, mirror_sidecar_result) {
+            (Some(sidecar_result), Some(mirror_sidecar_result)) => {
+                Some(mode.combine(sidecar_result, mirror_sidecar_result))
+            }
+            (sidecar_result, mirror_sidecar_result) => sidecar_result.or(mirror_sidecar_result),
+        };
+        (mode.combine(result, mirror_result), sidecar_result)
+    }
+
+    // Uploads the object, then its sidecar if there is one
+    async fn upload_to(
+        &self,
+        s3client: &S3Client,
+        element: &gst::Element,
+    ) -> (PutResult, Option<PutResult>) {
+        let handler: PutObjectHandler = self.settings.retry_handler(
+            element,
+            Some(self.frame_num),
+            format!("frame {}", self.frame_num),
+        );
+        let threshold = self.settings.multipart_threshold;
+        let result = if threshold > 0 && self.body.len() as u64 > threshold {
+            self.upload_multipart(s3client, element).await
+        } else {
+            FutureRetry::new(
+                || {
+                    let put_request = S3MultiFrameSink::create_put_object_request(self);
+                    s3client.put_object(put_request)
+                },
+                handler,
+            )
+            .await
+            .map(|_| ())
+            .map_err(|(error, _)| error)
+        };
+        let sidecar_result = match (&result, &self.sidecar) {
+            (Ok(()), Some(sidecar)) => Some(self.upload_sidecar(s3client, element, sidecar).await),
+            _ => None,
+        };
+        (result, sidecar_result)
+    }
+
+    async fn upload_sidecar(
+        &self,
+        s3client: &S3Client,
+        element: &gst::Element,
+        (key, body): &(String, Vec<u8>),
+    ) -> Result<(), RusotoError<PutObjectError>> {
+        let handler: PutObjectHandler = self.settings.retry_handler(
+            element,
+            Some(self.frame_num),
+            format!("sidecar of frame {}", self.frame_num),
+        );
+        FutureRetry::new(
+            || {
+                s3client.put_object(PutObjectRequest {
+                    body: Some(body.clone().into()),
+                    content_md5: if self.settings.needs_content_md5() {
+                        Some(base64::encode(md5::compute(body).0))
+                    } else {
+                        None
+                    },
+                    key: key.clone(),
+                    content_type: Some("application/json".to_string()),
+                    content_disposition: None,
+                    content_encoding: None,
+                    metadata: None,
+                    ..S3MultiFrameSink::create_object_headers(self)
+                })
+            },
+            handler,
+        )
+        .await
+        .map(|_| ())
+        .map_err(|(error, _)| error)
+    }
+
+    async fn upload_multipart(
+        &self,
+        s3client: &S3Client,
+        element: &gst::Element,
+    ) -> Result<(), RusotoError<PutObjectError>> {
+        let headers = S3MultiFrameSink::create_object_headers(self);
+        let bucket = headers.bucket.clone();
+        let key = headers.key.clone();
+        let request_payer = headers.request_payer.clone();
+        let creation = s3client
+            .create_multipart_upload(CreateMultipartUploadRequest {
+                acl: headers.acl,
+                bucket: headers.bucket,
+                cache_control: headers.cache_control,
+                content_disposition: headers.content_disposition,
+                content_encoding: headers.content_encoding,
+                content_language: headers.content_language,
+                content_type: headers.content_type,
+                expires: headers.expires,
+                grant_full_control: headers.grant_full_control,
+                grant_read: headers.grant_read,
+                grant_read_acp: headers.grant_read_acp,
+                grant_write_acp: headers.grant_write_acp,
+                key: headers.key,
+                metadata: headers.metadata,
+                object_lock_legal_hold_status: headers.object_lock_legal_hold_status,
+                object_lock_mode: headers.object_lock_mode,
+                object_lock_retain_until_date: headers.object_lock_retain_until_date,
+                request_payer: headers.request_payer,
+                sse_customer_algorithm: headers.sse_customer_algorithm,
+                sse_customer_key: headers.sse_customer_key,
+                sse_customer_key_md5: headers.sse_customer_key_md5,
+                ssekms_encryption_context: headers.ssekms_encryption_context,
+                ssekms_key_id: headers.ssekms_key_id,
+                server_side_encryption: headers.server_side_encryption,
+                storage_class: headers.storage_class,
+                tagging: headers.tagging,
+                website_redirect_location: headers.website_redirect_location,
+            })
+            .await
+            .map_err(into_put_object_error)?;
+        let upload_id = creation
+            .upload_id
+            .ok_or_else(|| RusotoError::ParseError("No upload ID in response".to_string()))?;
+
+        let part_size = MULTIPART_PART_SIZE.max(self.body.len() / MULTIPART_MAX_PARTS + 1);
+        let mut parts = Vec::new();
+        for (index, start) in (0..self.body.len()).step_by(part_size).enumerate() {
+            let part_number = index as i64 + 1;
+            let range = start..self.body.len().min(start + part_size);
+            let chunk = &self.body[range.clone()];
+            let handler = self.settings.retry_handler(
+                element,
+                Some(self.frame_num),
+                format!("part {} of frame {}", part_number, self.frame_num),
+            );
+            let part = FutureRetry::new(
+                || {
+                    s3client.upload_part(UploadPartRequest {
+                        body: Some(self.body.stream(range.clone())),
+                        bucket: bucket.clone(),
+                        content_length: Some(chunk.len() as i64),
+                        content_md5: if self.settings.needs_content_md5() {
+                            Some(base64::encode(md5::compute(chunk).0))
+                        } else {
+                            None
+                        },
+                        key: key.clone(),
+                        part_number,
+                        request_payer: request_payer.clone(),
+                        upload_id: upload_id.clone(),
+                        ..Default::default()
+                    })
+                },
+                handler,
+            )
+            .await;
+            match part {
+                Ok((output, _)) => parts.push(CompletedPart {
+                    e_tag: output.e_tag,
+                    part_number: Some(part_number),
+                }),
+                Err((error, _)) => {
+                    // Uploaded parts are billed until the upload is aborted
+                    let _ = s3client
+                        .abort_multipart_upload(AbortMultipartUploadRequest {
+                            bucket: bucket.clone(),
+                            key: key.clone(),
+                            request_payer: request_payer.clone(),
+                            upload_id: upload_id.clone(),
+                        })
+                        .await;
+                    return Err(into_put_object_error(error));
+                }
+            }
+        }
+
+        let completion = s3client
+            .complete_multipart_upload(CompleteMultipartUploadRequest {
+                bucket: bucket.clone(),
+                key: key.clone(),
+                multipart_upload: Some(CompletedMultipartUpload { parts: Some(parts) }),
+                request_payer: request_payer.clone(),
+                upload_id: upload_id.clone(),
+            })
+            .await;
+        if completion.is_err() {
+            // Including when no-overwrite finds the key taken
+            let _ = s3client
+                .abort_multipart_upload(AbortMultipartUploadRequest {
+                    bucket,
+                    key,
+                    request_payer,
+                    upload_id,
+                })
+                .await;
+        }
+        completion.map(|_| ()).map_err(into_put_object_error)
+    }
+}
+
+// Reports multipart failures like PutObject ones. None of the multipart
+// operations define service errors, so the conversion loses nothing.
+fn into_put_object_error<E: std::error::Error + 'static>(
+    error: RusotoError<E>,
+) -> RusotoError<PutObjectError> {
+    match error {
+        RusotoError::Service(error) => RusotoError::Validation(error.to_string()),
+        RusotoError::HttpDispatch(error) => RusotoError::HttpDispatch(error),
+        RusotoError::Credentials(error) => RusotoError::Credentials(error),
+        RusotoError::Validation(message) => RusotoError::Validation(message),
+        RusotoError::ParseError(message) => RusotoError::ParseError(message),
+        RusotoError::Unknown(response) => RusotoError::Unknown(response),
+        RusotoError::Blocking => RusotoError::Blocking,
+    }
+}
+
+pub fn register(plugin: &gst::Plugin) -> Result<(), glib::BoolError> {
+    gst::Element::register(
+        Some(plugin),
+        "s3multiframesink",
+        gst::Rank::None,
+        S3MultiFrameSink::get_type(),
+    )
+}
+
+impl S3MultiFrameSink {
+    // A panic while any lock is held poisons it. The state, settings and stats
+    // are still usable afterwards, so carry on rather than wedging the element.
+    fn lock_state(&self) -> MutexGuard<'_, State> {
+        self.state.lock().unwrap_or_else(|error| {
+            gst_warning!(CAT, "Recovering the state from a panic");
+            error.into_inner()
+        })
+    }
+
+    fn lock_settings(&self) -> MutexGuard<'_, Settings> {
+        self.settings.lock().unwrap_or_else(|error| {
+            gst_warning!(CAT, "Recovering the settings from a panic");
+            error.into_inner()
+        })
+    }
+
+    fn lock_stats(&self) -> MutexGuard<'_, Stats> {
+        self.stats.lock().unwrap_or_else(|error| {
+            gst_warning!(CAT, "Recovering the stats from a panic");
+            error.into_inner()
+        })
+    }
+
+    // Buffers rendered together are uploaded concurrently
+    fn render_buffers(
+        &self,
+        element: &gst_base::BaseSink,
+        buffers: &[&gst::BufferRef],
+    ) -> Result<gst::FlowSuccess, gst::FlowError> {
+        let mut state = self.lock_state();
+        let started = match *state {
+            State::Started(ref mut started) => started,
+            State::Stopped => {
+                gst_element_error!(element, gst::CoreError::Failed, ["Not started yet"]);
+                return Err(gst::FlowError::Error);
+            }
+        };
+        let media_type = match started.media_type {
+            Some(ref media_type) => media_type.clone(),
+            None => {
+                gst_element_error!(element, gst::CoreError::Negotiation, ["No caps negotiated"]);
+                return Err(gst::FlowError::NotNegotiated);
+            }
+        };
+
+        let max_total_bytes = self.lock_settings().max_total_bytes;
+        if max_total_bytes > 0 && started.bytes_uploaded >= max_total_bytes {
+            return Err(gst::FlowError::Eos);
+        }
+
+        let paused = self.lock_settings().paused;
+        let mut frames = Vec::new();
+        if !paused && !started.paused_frames.is_empty() {
+            gst_info!(
+                CAT,
+                obj: element,
+                "Resuming with {} uploads held while paused",
+                started.paused_frames.len()
+            );
+            frames.extend(started.paused_frames.drain(..));
+        }
+        for buffer in buffers {
+            let queued = self.queue_frame(element, started, &media_type, buffer)?;
+            if paused {
+                started.paused_frames.extend(queued);
+            } else {
+                frames.extend(queued);
+            }
+        }
+
+        let mut results = started.uploads.completed();
+        let submitted = S3MultiFrameSink::submit_uploads(
+            element,
+            started,
+            frames,
+            &mut results,
+            &self.canceller,
+        );
+        let (uploaded, failure) = self.record_uploads(element, started, results);
+        let bytes_uploaded = started.bytes_uploaded;
+        // Release the state before emitting so handlers may query the element
+        drop(state);
+        S3MultiFrameSink::emit_frames_uploaded(element, uploaded);
+        if let Err(Unlocked) = submitted {
+            gst_debug!(CAT, obj: element, "Uploads interrupted by unlock");
+            return Err(gst::FlowError::Flushing);
+        }
+
+        match failure {
+            Some((frame_num, error)) => {
+                gst_element_error!(
+                    element,
+                    gst::ResourceError::Write,
+                    ["Failed to upload frame {}: {}", frame_num, error]
+                );
+                Err(gst::FlowError::Error)
+            }
+            // Asking upstream for EOS lets the pipeline finish the frames it
+            // already sent instead of failing
+            None if max_total_bytes > 0 && bytes_uploaded >= max_total_bytes => {
+                gst_element_warning!(
+                    element,
+                    gst::ResourceError::NoSpaceLeft,
+                    [
+                        "Uploaded {} bytes, reaching max-total-bytes of {}",
+                        bytes_uploaded,
+                        max_total_bytes
+                    ]
+                );
+                Err(gst::FlowError::Eos)
+            }
+            None => Ok(gst::FlowSuccess::Ok),
+        }
+    }
+
+    // Numbers the buffer as the next frame, or each image it completes when
+    // reframing JPEG, returning what is ready for upload
+    fn queue_frame(
+        &self,
+        element: &gst_base::BaseSink,
+        started: &mut Started,
+        media_type: &str,
+        buffer: &gst::BufferRef,
+    ) -> Result<Vec<FrameUpload>, gst::FlowError> {
+        gst_trace!(CAT, obj: element, "Rendering {:?}", buffer);
+
+        let (async_uploads, drop_on_backpressure, paused, paused_queue_size, zero_copy_threshold) = {
+            let settings = self.lock_settings();
+            (
+                settings.async_uploads,
+                settings.drop_on_backpressure,
+                settings.paused,
+                settings.paused_queue_size as usize,
+                settings.zero_copy_threshold,
+            )
+        };
+        let backed_up = async_uploads && drop_on_backpressure && started.uploads.is_full();
+        let paused_full = paused && started.paused_frames.len() >= paused_queue_size;
+        // Dropped before numbering, so the frame numbers of uploads stay contiguous
+        if backed_up || paused_full {
+            self.lock_stats().frames_dropped += 1;
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Dropping {:?} while {}",
+                buffer,
+                if backed_up {
+                    "uploads are backed up"
+                } else {
+                    "paused"
+                }
+            );
+            // The image being reassembled is missing this buffer's bytes now
+            if let Some(ref mut reframer) = started.jpeg_reframer {
+                reframer.reset();
+            }
+            return Ok(Vec::new());
+        }
+        // Mapped from a new reference, so the mapping can outlive render
+        let map = buffer
+            .to_owned()
+            .into_mapped_buffer_readable()
+            .map_err(|_| {
+                gst_element_error!(element, gst::CoreError::Failed, ["Failed to map buffer"]);
+                gst::FlowError::Error
+            })?;
+        if map.get_size() == 0 && !self.lock_settings().allow_empty_frames {
+            gst_warning!(CAT, obj: element, "Skipping empty buffer {:?}", buffer);
+            return Ok(Vec::new());
+        }
+        if let Some(ref info) = started.video_info {
+            // Raw frames are uploaded as-is, so they must cover the negotiated layout
+            if map.get_size() < info.size() {
+                gst_element_error!(
+                    element,
+                    gst::StreamError::Format,
+                    [
+                        "Raw frame of {} bytes is smaller than the negotiated {} bytes",
+                        map.get_size(),
+                        info.size()
+                    ]
+                );
+                return Err(gst::FlowError::Error);
+            }
+        }
+        let pts = buffer.get_pts();
+        let running_time = element
+            .get_segment()
+            .downcast_ref::<gst::ClockTime>()
+            .and_then(|segment| segment.to_running_time(pts).nseconds());
+        let times = FrameTimes {
+            pts: pts.nseconds(),
+            running_time,
+            meta: self.reference_timestamp(buffer),
+        };
+        // Reassembled images take the timestamps of the buffer that completed them
+        let images = match started.jpeg_reframer {
+            Some(ref mut reframer) if media_type == "image/jpeg" => reframer
+                .push(map.as_slice())
+                .into_iter()
+                .map(FrameBody::from)
+                .collect(),
+            _ if map.get_size() as u64 <= zero_copy_threshold => {
+                vec![map.as_slice().to_vec().into()]
+            }
+            _ => vec![FrameBody::Mapped(Arc::new(map))],
+        };
+        let mut frames = Vec::new();
+        for image in images {
+            if let Some(frame) = self.number_frame(element, started, media_type, times, image)? {
+                frames.push(frame);
+            }
+        }
+        Ok(frames)
+    }
+
+    // Gives the frame the next number, returning what is ready for upload: the
+    // frame itself, a full batch, or nothing
+    fn number_frame(
+        &self,
+        element: &gst_base::BaseSink,
+        started: &mut Started,
+        media_type: &str,
+        times: FrameTimes,
+        body: FrameBody,
+    ) -> Result<Option<FrameUpload>, gst::FlowError> {
+        let frame_interval = u64::from(self.lock_settings().frame_interval);
+        let position = started.frames_seen % frame_interval;
+        started.frames_seen += 1;
+        // Skipped before numbering, so kept frames are numbered contiguously
+        if position > 0 {
+            gst_trace!(
+                CAT,
+                obj: element,
+                "Skipping frame {} of every {}",
+                position + 1,
+                frame_interval
+            );
+            return Ok(None);
+        }
+        if self.lock_settings().skip_duplicate_frames {
+            let hash: [u8; 32] = Sha256::digest(&body).into();
+            // Skipped before numbering, like empty buffers
+            if let Some((last_hash, last_frame_num)) = started.last_frame_hash {
+                if last_hash == hash {
+                    gst_debug!(
+                        CAT,
+                        obj: element,
+                        "Skipping frame identical to frame {}",
+                        last_frame_num
+                    );
+                    return Ok(None);
+                }
+            }
+            started.last_frame_hash = Some((hash, started.frame_num));
+        }
+        let frame = self.prepare_frame(started, media_type, times, body);
+        started.frame_num += 1;
+
+        if started.batch_size <= 1 {
+            return Ok(Some(frame));
+        }
+        // Archived frames are named after the last component of their key
+        let key_separator = frame.settings.key_separator.as_str();
+        let name = match key_separator {
+            "" => frame.key.as_str(),
+            separator => frame.key.rsplit(separator).next().unwrap_or_default(),
+        };
+        let mtime = frame.wallclock_ms / 1000;
+        if let Err(error) = started
+            .batch
+            .archive
+            .append(name, &frame.body, mtime as u64)
+        {
+            gst_element_error!(
+                element,
+                gst::ResourceError::Write,
+                [
+                    "Failed to add frame {} to batch: {}",
+                    frame.frame_num,
+                    error
+                ]
+            );
+            return Err(gst::FlowError::Error);
+        }
+        started.batch.last_frame = Some((frame.frame_num, frame.pts));
+        started.batch.entries.extend(frame.entries);
+        if started.batch.archive.entries() >= started.batch_size {
+            Ok(self.take_batch(started))
+        } else {
+            Ok(None)
+        }
+    }
+
+    fn create_s3client(settings: &Settings) -> Result<S3Client, gst::ErrorMessage> {
+        let region = match settings.endpoint {
+            Some(ref endpoint) => Region::Custom {
+                name: settings.region.name().to_string(),
+                endpoint: endpoint.clone(),
+            },
+            None => settings.region.clone(),
+        };
+
+        let timeout = match settings.request_timeout_ms {
+            0 => None,
+            timeout_ms => Some(Duration::from_millis(u64::from(timeout_ms))),
+        };
+        let headers = settings
+            .custom_headers
+            .as_deref()
+            .map(|headers| {
+                request_dispatcher::parse_headers(headers)
+                    .expect("Custom headers are validated when set")
+            })
+            .unwrap_or_default();
+        // The connector also speaks plain HTTP, so an http:// endpoint is used
+        // as given, e.g. for a local MinIO without TLS
+        let dispatcher = || {
+            HttpClient::new()
+                .map(|client| {
+                    RequestDispatcher::new(client, timeout, settings.no_overwrite, headers.clone())
+                })
+                .map_err(|error| {
+                    gst_error_msg!(
+                        gst::ResourceError::Settings,
+                        ["Failed to create HTTP client: {}", error]
+                    )
+                })
+        };
+        // Static keys take precedence; start() warns when a profile is set as well
+        if let (Some(access_key), Some(secret_key)) =
+            (settings.access_key.as_ref(), settings.secret_key.as_ref())
+        {
+            let credentials = StaticProvider::new_minimal(access_key.clone(), secret_key.clone());
+            Ok(S3Client::new_with(dispatcher()?, credentials, region))
+        } else if let Some(ref role_arn) = settings.assume_role_arn {
+            // The role is assumed with credentials from the default chain, and the
+            // temporary credentials are refreshed before they expire
+            let sts_client = StsClient::new(settings.region.clone());
+            let provider = StsAssumeRoleSessionCredentialsProvider::new(
+                sts_client,
+                role_arn.clone(),
+                "gst-s3multiframesink".to_string(),
+                settings.assume_role_external_id.clone(),
+                None,
+                None,
+                None,
+            );
+            let credentials = AutoRefreshingProvider::new(provider).map_err(|error| {
+                gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Failed to assume role {}: {}", role_arn, error]
+                )
+            })?;
+            Ok(S3Client::new_with(dispatcher()?, credentials, region))
+        } else if let Some(ref profile) = settings.profile {
+            let credentials =
+                ProfileProvider::with_default_credentials(profile.clone()).map_err(|error| {
+                    gst_error_msg!(
+                        gst::ResourceError::Settings,
+                        ["Failed to load AWS profile {}: {}", profile, error]
+                    )
+                })?;
+            Ok(S3Client::new_with(dispatcher()?, credentials, region))
+        } else {
+            let credentials = DefaultCredentialsProvider::new().map_err(|error| {
+                gst_error_msg!(
+                    gst::ResourceError::Settings,
+                    ["Failed to set up AWS credentials: {}", error]
+                )
+            })?;
+            Ok(S3Client::new_with(dispatcher()?, credentials, region))
+        }
+    }
+
+    // The source timestamp attached by upstream, e.g. by a network source with
+    // add-reference-timestamp-meta, for the {meta} key token
+    fn reference_timestamp(&self, buffer: &gst::BufferRef) -> Option<u64> {
+        let settings = self.lock_settings();
+        buffer
+            .iter_meta::<gst::meta::ReferenceTimestampMeta>()
+            .find(|meta| match settings.meta_reference {
+                Some(ref reference) => {
+                    meta.get_reference()
+                        .get_structure(0)
+                        .map(|structure| structure.get_name())
+                        == Some(reference.as_str())
+                }
+                None => true,
+            })
+            .and_then(|meta| meta.get_timestamp().nseconds())
+    }
+
+    fn prepare_frame(
+        &self,
+        started: &mut Started,
+        media_type: &str,
+        times: FrameTimes,
+        body: FrameBody,
+    ) -> FrameUpload {
+        let mut settings = self.lock_settings().clone();
+        // key-extension is used verbatim, so it can change the case or drop the
+        // extension entirely. Otherwise an explicit extension property wins over
+        // the one derived from the caps.
+        let suffix = match settings.key_extension {
+            Some(ref key_extension) => key_extension.clone(),
+            None => {
+                let extension = settings
+                    .extension
+                    .as_deref()
+                    .or_else(|| media_type_extension(media_type))
+                    .unwrap();
+                format!(".{}", extension)
+            }
+        };
+        // Rendered once per frame so that {timestamp} stays stable across retries
+        let wallclock_ms = SystemTime::now()
+            .duration_since(UNIX_EPOCH)
+            .map(|elapsed| elapsed.as_millis())
+            .unwrap_or_default();
+        // Buffers without the chosen timestamp fall back to the wall clock
+        let timestamp = match settings.timestamp_source {
+            TimestampSource::Pts => times.pts,
+            TimestampSource::RunningTime => times.running_time,
+            TimestampSource::Wallclock => None,
+        };
+        let timestamp_ms = timestamp.map_or(wallclock_ms, |ns| u128::from(ns / 1_000_000));
+        // PTS and running time count from the start of the stream, so they're
+        // anchored to the wall clock to give calendar dates
+        let calendar_ms = match timestamp {
+            Some(ns) => {
+                let source = settings.timestamp_source;
+                let anchor = match started.clock_anchor {
+                    Some(anchor) if anchor.source == source => anchor,
+                    _ => *started.clock_anchor.insert(ClockAnchor {
+                        source,
+                        ns,
+                        wallclock_ms,
+                    }),
+                };
+                anchor.wallclock_ms(ns)
+            }
+            None => wallclock_ms,
+        };
+        let frame_num = started.frame_num;
+        let mut prefix = settings.key_prefix().to_string();
+        if settings.reset_on_segment && started.segment_index > 0 {
+            write!(
+                prefix,
+                "{}seg{:03}",
+                settings.key_separator, started.segment_index
+            )
+            .unwrap();
+        }
+        // Caps may change mid-stream, so this follows the latest negotiation.
+        // set_caps refuses caps without dimensions while this is enabled.
+        if let (true, Some((width, height))) = (settings.resolution_in_key, started.dimensions) {
+            write!(prefix, "{}{}x{}", settings.key_separator, width, height).unwrap();
+        }
+        if settings.date_partition {
+            let partition =
+                date_partition::partition(settings.date_partition_granularity, calendar_ms);
+            write!(prefix, "{}{}", settings.key_separator, partition).unwrap();
+        }
+        let timestamp = settings.render_timestamp(timestamp_ms);
+        let fields = KeyFields {
+            prefix: &prefix,
+            frame_num,
+            pts: times.pts,
+            timestamp: &timestamp,
+            meta: times.meta,
+            key_suffix: settings.key_suffix.as_deref().unwrap_or_default(),
+        };
+        let suffix = if settings.hash_in_key {
+            // Hashes the frame as rendered, before any compression
+            format!("-{}{}", short_hash(&body), suffix)
+        } else {
+            suffix
+        };
+        let mut key = started.key_template.render(&fields, &suffix);
+        let content_disposition = started
+            .content_disposition
+            .as_ref()
+            .map(|template| template.expand(&fields));
+        let body = if settings.compress {
+            key.push_str(".gz");
+            // Encodings are listed in the order they were applied
+            settings.content_encoding = Some(match settings.content_encoding {
+                Some(ref encoding) => format!("{}, gzip", encoding),
+                None => "gzip".to_string(),
+            });
+            gzip(&body).into()
+        } else {
+            body
+        };
+
+        // Archived frames have no object of their own to sit next to
+        let sidecar = if settings.write_sidecar && started.batch_size <= 1 {
+            let body = json!({
+                "frame": frame_num,
+                "key": key,
+                "pts": times.pts,
+                "caps": started.caps,
+            });
+            Some((
+                started.key_template.render(&fields, ".json"),
+                serde_json::to_vec(&body).expect("JSON values always serialize"),
+            ))
+        } else {
+            None
+        };
+
+        let body_len = body.len();
+        FrameUpload {
+            frame_num,
+            pts: times.pts,
+            key,
+            content_type: media_type_content_type(media_type).to_string(),
+            content_disposition,
+            body,
+            frames: 1,
+            wallclock_ms,
+            timestamp: Some(timestamp),
+            sidecar,
+            entries: vec![ManifestEntry {
+                frame_num,
+                pts: times.pts,
+                size: body_len,
+            }],
+            settings,
+        }
+    }
+
+    // Uploads in the background, blocks on the uploads, or only logs them in
+    // dry-run mode. Frames not yet submitted when unlocked are abandoned.
+    fn submit_uploads(
+        element: &gst_base::BaseSink,
+        started: &mut Started,
+        frames: Vec<FrameUpload>,
+        results: &mut Vec<UploadResult>,
+        canceller: &Canceller,
+    ) -> Result<(), Unlocked> {
+        let s3client = match started.s3client {
+            Some(ref s3client) => s3client,
+            None => {
+                for frame in frames {
+                    gst_info!(
+                        CAT,
+                        obj: element,
+                        "Dry run: would upload {} bytes to {}/{}",
+                        frame.body.len(),
+                        frame.settings.bucket.as_deref().unwrap_or_default(),
+                        frame.key
+                    );
+                    if let Some((ref key, _)) = frame.sidecar {
+                        gst_info!(CAT, obj: element, "Dry run: would upload sidecar {}", key);
+                    }
+                    results.push(UploadResult {
+                        frame_num: frame.frame_num,
+                        key: frame.key,
+                        frames: frame.frames,
+                        size: frame.body.len(),
+                        elapsed: Duration::from_secs(0),
+                        entries: frame.entries,
+                        result: Ok(()),
+                        sidecar_result: None,
+                    });
+                }
+                return Ok(());
+            }
+        };
+
+        let mut blocking = Vec::new();
+        for frame in frames {
+            if let Some(ref mut rate_limiter) = started.rate_limiter {
+                let wait = rate_limiter.acquire();
+                if wait > Duration::from_secs(0) {
+                    gst_trace!(CAT, obj: element, "Throttling upload for {:?}", wait);
+                    std::thread::sleep(wait);
+                }
+            }
+            let async_uploads = frame.settings.async_uploads;
+            let upload = frame.upload(
+                s3client.clone(),
+                started.mirror_s3client.clone(),
+                element.clone().upcast(),
+            );
+            if async_uploads {
+                let slot = canceller.block_on(&started.runtime, started.uploads.slot())?;
+                started.uploads.spawn(slot, upload);
+            } else {
+                blocking.push(upload);
+            }
+        }
+        if !blocking.is_empty() {
+            results.extend(canceller.block_on(&started.runtime, future::join_all(blocking))?);
+        }
+        Ok(())
+    }
+
+    // Restarts frame numbering for reset-on-segment. A partial batch is
+    // uploaded first, since its frames' names would repeat in the next one.
+    fn start_segment(&self, element: &gst_base::BaseSink) -> bool {
+        let mut state = self.lock_state();
+        let started = match *state {
+            State::Started(ref mut started) => started,
+            State::Stopped => return true,
+        };
+        let mut results = started.uploads.completed();
+        let frames: Vec<_> = self.take_batch(started).into_iter().collect();
+        let submitted = S3MultiFrameSink::submit_uploads(
+            element,
+            started,
+            frames,
+            &mut results,
+            &self.canceller,
+        );
+        let (uploaded, failure) = self.record_uploads(element, started, results);
+        started.segment_index += 1;
+        started.frame_num = self.lock_settings().start_index;
+        // The new segment's timestamps may start over
+        started.clock_anchor = None;
+        gst_debug!(CAT, obj: element, "Starting segment {}", started.segment_index);
+        drop(state);
+        S3MultiFrameSink::emit_frames_uploaded(element, uploaded);
+
+        if let Err(Unlocked) = submitted {
+            gst_debug!(CAT, obj: element, "Uploads interrupted by unlock");
+            return false;
+        }
+        if let Some((frame_num, error)) = failure {
+            gst_element_error!(
+                element,
+                gst::ResourceError::Write,
+                ["Failed to upload frame {}: {}", frame_num, error]
+            );
+            return false;
+        }
+        true
+    }
+
+    // Uploads the partial batch and waits for every pending upload, unless
+    // unlocked. Whatever finished is recorded either way.
+    fn finish_uploads(
+        &self,
+        element: &gst_base::BaseSink,
+        started: &mut Started,
+    ) -> FinishedUploads {
+        if let Some(ref mut reframer) = started.jpeg_reframer {
+            if reframer.pending() > 0 {
+                gst_warning!(
+                    CAT,
+                    obj: element,
+                    "Discarding {} bytes of an incomplete JPEG",
+                    reframer.pending()
+                );
+                reframer.reset();
+            }
+        }
+        let mut results = Vec::new();
+        // Held frames are uploaded even if still paused, rather than lost
+        let mut frames: Vec<_> = started.paused_frames.drain(..).collect();
+        if let Some(frame) = self.take_batch(started) {
+            frames.push(frame);
+        }
+        let mut finished = S3MultiFrameSink::submit_uploads(
+            element,
+            started,
+            frames,
+            &mut results,
+            &self.canceller,
+        );
+        if finished.is_ok() {
+            match self
+                .canceller
+                .block_on(&started.runtime, started.uploads.drain())
+            {
+                Ok(drained) => results.extend(drained),
+                Err(unlocked) => finished = Err(unlocked),
+            }
+        }
+        let (uploaded, failure) = self.record_uploads(element, started, results);
+        (uploaded, failure, finished)
+    }
+
+    // Uploads manifest.json listing every frame since start, reusing the frame retry policy
+    fn upload_manifest(
+        &self,
+        element: &gst_base::BaseSink,
+        started: &mut Started,
+    ) -> Result<(), gst::ErrorMessage> {
+        let entries = match started.manifest.take() {
+            Some(entries) => entries,
+            None => return Ok(()),
+        };
+        let mut settings = self.lock_settings().clone();
+        // Frame metadata doesn't describe the manifest itself
+        settings.write_metadata = false;
+        let manifest = FrameUpload {
+            frame_num: started.frame_num,
+            pts: None,
+            key: settings.object_key("manifest.json"),
+            content_type: "application/json".to_string(),
+            content_disposition: None,
+            body: serde_json::to_vec(&json!({ "frames": entries }))
+                .expect("JSON values always serialize")
+                .into(),
+            frames: 0,
+            wallclock_ms: 0,
+            timestamp: None,
+            sidecar: None,
+            entries: Vec::new(),
+            settings,
+        };
+
+        match started.s3client {
+            None => {
+                gst_info!(
+                    CAT,
+                    obj: element,
+                    "Dry run: would upload a {} byte manifest to {}",
+                    manifest.body.len(),
+                    manifest.key
+                );
+                Ok(())
+            }
+            Some(ref s3client) => {
+                let upload = started.runtime.handle().block_on(manifest.upload(
+                    s3client.clone(),
+                    started.mirror_s3client.clone(),
+                    element.clone().upcast(),
+                ));
+                match upload.result {
+                    Ok(()) => Ok(()),
+                    // A resumed capture keeps the manifest of its first run under no-overwrite
+                    Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 412 => {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Not replacing existing manifest {}",
+                            upload.key
+                        );
+                        Ok(())
+                    }
+                    Err(error) => Err(gst_error_msg!(
+                        gst::ResourceError::Write,
+                        ["Failed to upload manifest: {}", error]
+                    )),
+                }
+            }
+        }
+    }
+
+    // Closes the pending archive, if any frames were added to it
+    fn take_batch(&self, started: &mut Started) -> Option<FrameUpload> {
+        let (frame_num, pts) = started.batch.last_frame.take()?;
+        let index = started.batch.index;
+        let archive = std::mem::take(&mut started.batch.archive);
+        started.batch.index += 1;
+
+        let settings = self.lock_settings().clone();
+        Some(FrameUpload {
+            frame_num,
+            pts,
+            key: settings.object_key(&format!("batch{:010}.tar", index)),
+            content_type: "application/x-tar".to_string(),
+            content_disposition: None,
+            frames: archive.entries(),
+            body: archive.finish().into(),
+            wallclock_ms: 0,
+            timestamp: None,
+            sidecar: None,
+            entries: std::mem::take(&mut started.batch.entries),
+            settings,
+        })
+    }
+
+    fn record_uploads(
+        &self,
+        element: &gst_base::BaseSink,
+        started: &mut Started,
+        results: Vec<UploadResult>,
+    ) -> (Vec<(u64, String)>, Option<UploadFailure>) {
+        let mut uploaded = Vec::new();
+        let mut failure = None;
+        for upload in results {
+            gst_debug!(
+                CAT,
+                obj: element,
+                "Upload of frame {} ({} bytes) took {} ms",
+                upload.frame_num,
+                upload.size,
+                upload.elapsed.as_millis()
+            );
+            match upload.result {
+                Ok(()) => {
+                    started.bytes_uploaded += upload.size as u64;
+                    let mut stats = self.lock_stats();
+                    stats.frames_uploaded += upload.frames;
+                    // Dry runs never reach S3, so they don't count towards the average
+                    if started.s3client.is_some() {
+                        if stats.upload_times.len() == UPLOAD_TIMES_WINDOW {
+                            stats.upload_times.pop_front();
+                        }
+                        stats.upload_times.push_back(upload.elapsed);
+                    }
+                    stats.last_key = Some(upload.key.clone());
+                    drop(stats);
+                    started.consecutive_failures = 0;
+                    if let Some(ref mut manifest) = started.manifest {
+                        manifest.extend(upload.entries.iter().map(|entry| {
+                            json!({
+                                "frame": entry.frame_num,
+                                "key": upload.key,
+                                "pts": entry.pts,
+                                "size": entry.size,
+                            })
+                        }));
+                    }
+                    match upload.sidecar_result {
+                        Some(Err(error)) if started.sidecar_failure_fatal => {
+                            failure.get_or_insert((upload.frame_num, error));
+                        }
+                        Some(Err(error)) => gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Failed to upload the sidecar of frame {}: {}",
+                            upload.frame_num,
+                            error
+                        ),
+                        _ => (),
+                    }
+                    uploaded.push((upload.frame_num, upload.key));
+                }
+                // Only sent with no-overwrite, when the key already exists
+                Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 412 => {
+                    gst_info!(
+                        CAT,
+                        obj: element,
+                        "Skipping frame {}, {} already exists",
+                        upload.frame_num,
+                        upload.key
+                    );
+                }
+                Err(error) => {
+                    started.uploads_failed += 1;
+                    started.consecutive_failures += 1;
+                    // Isolated failures lose the frame but keep the pipeline going
+                    if started.consecutive_failures < started.max_consecutive_failures {
+                        gst_warning!(
+                            CAT,
+                            obj: element,
+                            "Skipping frame {}, which failed to upload ({} in a row): {}",
+                            upload.frame_num,
+                            started.consecutive_failures,
+                            error
+                        );
+                    } else {
+                        failure.get_or_insert((upload.frame_num, error));
+                    }
+                }
+            }
+        }
+        (uploaded, failure)
+    }
+
+    fn emit_frames_uploaded(element: &gst_base::BaseSink, uploaded: Vec<(u64, String)>) {
+        for (frame_num, key) in uploaded {
+            element.emit("frame-uploaded", &[&frame_num, &key]).unwrap();
+        }
+    }
+
+    fn create_put_object_request(frame: &FrameUpload) -> PutObjectRequest {
+        PutObjectRequest {
+            body: Some(frame.body.stream(0..frame.body.len())),
+            content_md5: if frame.settings.needs_content_md5() {
+                Some(base64::encode(md5::compute(&*frame.body).0))
+            } else {
+                None
+            },
+            ..S3MultiFrameSink::create_object_headers(frame)
+        }
+    }
+
+    // Everything but the body of the request, shared with multipart uploads
+    fn create_object_headers(frame: &FrameUpload) -> PutObjectRequest {
+        let settings = &frame.settings;
+        let metadata = if settings.write_metadata {
+            let mut metadata = HashMap::new();
+            metadata.insert("frame-number".to_string(), frame.frame_num.to_string());
+            if let Some(pts) = frame.pts {
+                metadata.insert("pts".to_string(), pts.to_string());
+            }
+            if let Some(ref timestamp) = frame.timestamp {
+                metadata.insert("timestamp".to_string(), timestamp.clone());
+            }
+            Some(metadata)
+        } else {
+            None
+        };
+
+        PutObjectRequest {
+            bucket: settings
+                .bucket
+                .as_ref()
+                .expect("Bucket should be set by start time")
+                .clone(),
+            key: frame.key.clone(),
+            content_type: Some(frame.content_type.clone()),
+            content_disposition: frame.content_disposition.clone(),
+            content_encoding: settings.content_encoding.clone(),
+            cache_control: settings.cache_control.clone(),
+            server_side_encryption: settings.server_side_encryption.clone(),
+            ssekms_key_id: settings.sse_kms_key_id.clone(),
+            storage_class: settings.storage_class.clone(),
+            tagging: settings.tags.clone(),
+            acl: settings.object_acl.clone(),
+            request_payer: settings.request_payer.clone(),
+            object_lock_mode: settings
+                .object_lock_mode
+                .map(|mode| mode.name().to_string()),
+            object_lock_retain_until_date: settings.object_lock_retain_until.clone(),
+            expires: settings.expires.as_ref().map(Expires::header),
+            metadata,
+            ..Default::default()
+        }
+    }
+
+    // The highest frame number among the objects under the prefix, for
+    // resume. Keys that aren't named like frames are skipped.
+    fn last_frame_num(
+        &self,
+        element: &gst_base::BaseSink,
+        runtime: &runtime::Runtime,
+        s3client: &S3Client,
+        prefix: String,
+    ) -> Result<Option<u64>, gst::ErrorMessage> {
+        let (bucket, key_separator) = {
+            let settings = self.lock_settings();
+            let bucket = settings
+                .bucket
+                .clone()
+                .expect("Bucket should be set by start time");
+            (bucket, settings.key_separator.clone())
+        };
+        let list_error = |error: String| {
+            gst_error_msg!(
+                gst::ResourceError::Read,
+                [
+                    "Failed to list existing frames in {}/{}: {}",
+                    bucket,
+                    prefix,
+                    error
+                ]
+            )
+        };
+
+        runtime.handle().block_on(async {
+            let mut last = None;
+            let mut continuation_token = None;
+            loop {
+                let listing = s3client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: bucket.clone(),
+                        prefix: Some(prefix.clone()),
+                        continuation_token,
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(|error| list_error(error.to_string()))?;
+                for key in listing
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key)
+                {
+                    match key_frame_num(&key[prefix.len()..], &key_separator) {
+                        Some(frame_num) => last = last.max(Some(frame_num)),
+                        None => gst_trace!(CAT, obj: element, "Not a frame: {}", key),
+                    }
+                }
+                continuation_token = match listing.is_truncated {
+                    Some(true) => listing.next_continuation_token,
+                    _ => None,
+                };
+                if continuation_token.is_none() {
+                    return Ok(last);
+                }
+            }
         })
     }
+
+    // Looks up the bucket's actual region, returning it with a client for it.
+    // Keeps the configured region if the lookup fails.
+    fn detect_region(
+        &self,
+        element: &gst_base::BaseSink,
+        runtime: &runtime::Runtime,
+        s3client: S3Client,
+    ) -> Result<(S3Client, Region), gst::ErrorMessage> {
+        // A copy, so properties stay usable during the lookup
+        let mut settings = self.lock_settings().clone();
+        // Custom endpoints don't route by region
+        if settings.endpoint.is_some() {
+            return Ok((s3client, settings.region));
+        }
+        let bucket = settings
+            .bucket
+            .as_ref()
+            .expect("Bucket should be set by start time")
+            .clone();
+        let location = runtime
+            .handle()
+            .block_on(s3client.get_bucket_location(GetBucketLocationRequest { bucket }));
+        let location_constraint = match location {
+            Ok(output) => output.location_constraint,
+            Err(error) => {
+                gst_warning!(
+                    CAT,
+                    obj: element,
+                    "Failed to look up the bucket region, keeping {}: {}",
+                    settings.region.name(),
+                    error
+                );
+                return Ok((s3client, settings.region));
+            }
+        };
+        // Buckets in us-east-1 have no location constraint, and EU is a legacy alias
+        let region = match location_constraint.as_deref() {
+            None | Some("") => Region::UsEast1,
+            Some("EU") => Region::EuWest1,
+            Some(name) => match Region::from_str(name) {
+                Ok(region) => region,
+                Err(error) => {
+                    gst_warning!(
+                        CAT,
+                        obj: element,
+                        "Ignoring unknown bucket region {}: {}",
+                        name,
+                        error
+                    );
+                    return Ok((s3client, settings.region));
+                }
+            },
+        };
+        if region == settings.region {
+            return Ok((s3client, region));
+        }
+
+        gst_info!(
+            CAT,
+            obj: element,
+            "Bucket is in {}, not the configured {}",
+            region.name(),
+            settings.region.name()
+        );
+        settings.region = region.clone();
+        Ok((S3MultiFrameSink::create_s3client(&settings)?, region))
+    }
+
+    fn create_bucket_if_extant(
+        &self,
+        element: &gst_base::BaseSink,
+        runtime: &runtime::Runtime,
+        s3client: &S3Client,
+    ) -> Result<bool, gst::ErrorMessage> {
+        let settings = self.lock_settings();
+        let bucket = settings
+            .bucket
+            .as_ref()
+            .expect("Bucket should be set by start time")
+            .clone();
+        let result = runtime.handle().block_on(async {
+            if S3MultiFrameSink::bucket_exists(element, &settings, s3client, &bucket).await? {
+                return Ok(false);
+            }
+
+            let bucket_creation = FutureRetry::new(
+                || {
+                    s3client.create_bucket(CreateBucketRequest {
+                        acl: settings.bucket_acl.clone(),
+                        bucket: bucket.clone(),
+                        create_bucket_configuration: region::location_constraint(&settings.region)
+                            .map(|location_constraint| CreateBucketConfiguration {
+                                location_constraint: Some(location_constraint),
+                            }),
+                        grant_full_control: settings.bucket_grant_full_control.clone(),
+                        grant_read: settings.bucket_grant_read.clone(),
+                        grant_read_acp: None,
+                        grant_write: None,
+                        grant_write_acp: None,
+                        object_lock_enabled_for_bucket: None,
+                    })
+                },
+                settings.retry_handler(
+                    element.upcast_ref(),
+                    None,
+                    format!("creation of {}", bucket),
+                ),
+            )
+            .await
+            .map_err(|(error, _)| error);
+
+            let error = match bucket_creation {
+                Ok(_) => return Ok(true),
+                Err(RusotoError::Service(CreateBucketError::BucketAlreadyOwnedByYou(_))) => {
+                    return Ok(false)
+                }
+                Err(RusotoError::Service(CreateBucketError::BucketAlreadyExists(_)))
+                    if settings.accept_existing_bucket =>
+                {
+                    return Ok(false)
+                }
+                Err(error) => error,
+            };
+            Err(gst_error_msg!(
+                gst::ResourceError::Settings,
+                [&format!("{}", error)]
+            ))
+        });
+        match result {
+            // Lets the first upload tell whether writes work, e.g. when the
+            // credentials may write objects but not create buckets
+            Err(error) if settings.tolerate_create_bucket_errors => {
+                gst_warning!(
+                    CAT,
+                    obj: element,
+                    "Ignoring failure to create bucket {}: {}",
+                    bucket,
+                    error
+                );
+                Ok(false)
+            }
+            result => result,
+        }
+    }
+
+    // Fails start() right away when the credentials, region or endpoint are
+    // wrong, rather than on the first frame
+    fn check_bucket(
+        &self,
+        element: &gst_base::BaseSink,
+        runtime: &runtime::Runtime,
+        s3client: &S3Client,
+    ) -> Result<(), gst::ErrorMessage> {
+        let settings = self.lock_settings();
+        let bucket = settings
+            .bucket
+            .as_ref()
+            .expect("Bucket should be set by start time");
+        let exists = runtime.handle().block_on(S3MultiFrameSink::bucket_exists(
+            element, &settings, s3client, bucket,
+        ))?;
+        if !exists {
+            return Err(gst_error_msg!(
+                gst::ResourceError::NotFound,
+                [
+                    "Bucket {} does not exist and create-bucket is disabled",
+                    bucket
+                ]
+            ));
+        }
+        gst_debug!(CAT, obj: element, "Bucket {} is reachable", bucket);
+
+        Ok(())
+    }
+
+    // Warns when uploads set a retention the bucket can't apply, which S3
+    // rejects on every upload
+    fn check_object_lock(
+        &self,
+        element: &gst_base::BaseSink,
+        runtime: &runtime::Runtime,
+        s3client: &S3Client,
+    ) {
+        let bucket = self
+            .lock_settings()
+            .bucket
+            .clone()
+            .expect("Bucket should be set by start time");
+        let configuration = runtime
+            .handle()
+            .block_on(
+                s3client.get_object_lock_configuration(GetObjectLockConfigurationRequest {
+                    bucket: bucket.clone(),
+                }),
+            );
+        let enabled = match configuration {
+            Ok(output) => output
+                .object_lock_configuration
+                .and_then(|configuration| configuration.object_lock_enabled),
+            Err(error) => {
+                gst_warning!(
+                    CAT,
+                    obj: element,
+                    "Failed to get the object lock configuration of {}: {}",
+                    bucket,
+                    error
+                );
+                return;
+            }
+        };
+        if enabled.as_deref() == Some("Enabled") {
+            gst_debug!(CAT, obj: element, "Bucket {} has object lock enabled", bucket);
+        } else {
+            gst_warning!(
+                CAT,
+                obj: element,
+                "Bucket {} doesn't have object lock enabled, uploads will fail",
+                bucket
+            );
+        }
+    }
+
+    async fn bucket_exists(
+        element: &gst_base::BaseSink,
+        settings: &Settings,
+        s3client: &S3Client,
+        bucket: &str,
+    ) -> Result<bool, gst::ErrorMessage> {
+        let head = FutureRetry::new(
+            || {
+                s3client.head_bucket(HeadBucketRequest {
+                    bucket: bucket.to_string(),
+                })
+            },
+            settings.retry_handler(element.upcast_ref(), None, format!("check of {}", bucket)),
+        )
+        .await
+        .map(|_| ())
+        .map_err(|(error, _)| error);
+        match head {
+            Ok(()) => Ok(true),
+            Err(RusotoError::Service(HeadBucketError::NoSuchBucket(_))) => Ok(false),
+            // HEAD responses carry no body, so a missing bucket usually shows up
+            // as a bare 404 rather than NoSuchBucket
+            Err(RusotoError::Unknown(ref response)) if response.status.as_u16() == 404 => Ok(false),
+            // S3 answers 403 for buckets that exist but can't be listed, which
+            // doesn't stop us writing to them
+            Err(RusotoError::Unknown(ref response))
+                if response.status.as_u16() == 403 && settings.accept_existing_bucket =>
+            {
+                Ok(true)
+            }
+            Err(error) => Err(gst_error_msg!(
+                gst::ResourceError::Settings,
+                ["Failed to check bucket {}: {}", bucket, error]
+            )),
+        }
+    }
+
+    // Empties and removes the bucket, for throwaway buckets in test pipelines
+    fn delete_created_bucket(
+        &self,
+        element: &gst_base::BaseSink,
+        started: &Started,
+    ) -> Result<(), gst::ErrorMessage> {
+        let s3client = match started.s3client {
+            Some(ref s3client) => s3client,
+            None => return Ok(()),
+        };
+        let bucket = self
+            .lock_settings()
+            .bucket
+            .clone()
+            .expect("Bucket should be set by start time");
+        let delete_error = |error: String| {
+            gst_error_msg!(
+                gst::ResourceError::Write,
+                ["Failed to delete bucket {}: {}", bucket, error]
+            )
+        };
+
+        started.runtime.handle().block_on(async {
+            // Each pass deletes up to one page of objects, until none are left
+            loop {
+                let listing = s3client
+                    .list_objects_v2(ListObjectsV2Request {
+                        bucket: bucket.clone(),
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(|error| delete_error(error.to_string()))?;
+                let objects: Vec<ObjectIdentifier> = listing
+                    .contents
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter_map(|object| object.key)
+                    .map(|key| ObjectIdentifier {
+                        key,
+                        version_id: None,
+                    })
+                    .collect();
+                if objects.is_empty() {
+                    break;
+                }
+                gst_debug!(CAT, obj: element, "Deleting {} objects", objects.len());
+                let deletion = s3client
+                    .delete_objects(DeleteObjectsRequest {
+                        bucket: bucket.clone(),
+                        delete: Delete {
+                            objects,
+                            quiet: Some(true),
+                        },
+                        ..Default::default()
+                    })
+                    .await
+                    .map_err(|error| delete_error(error.to_string()))?;
+                if let Some(failed) = deletion.errors.and_then(|errors| errors.into_iter().next()) {
+                    return Err(delete_error(format!(
+                        "{}: {}",
+                        failed.key.unwrap_or_default(),
+                        failed.message.unwrap_or_default()
+                    )));
+                }
+            }
+            s3client
+                .delete_bucket(DeleteBucketRequest {
+                    bucket: bucket.clone(),
+                })
+                .await
+                .map_err(|error| delete_error(error.to_string()))
+        })?;
+        gst_info!(CAT, obj: element, "Deleted bucket {}", bucket);
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn settings(key: &str, key_separator: &str) -> Settings {
+        Settings {
+            key: Some(key.to_string()),
+            key_separator: key_separator.to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn frame_upload(media_type: &str) -> FrameUpload {
+        let mut settings = settings("captures", "/");
+        settings.bucket = Some("bucket".to_string());
+        FrameUpload {
+            frame_num: 1,
+            pts: Some(0),
+            key: settings.object_key("frame0000000001"),
+            content_type: media_type_content_type(media_type).to_string(),
+            content_disposition: None,
+            body: vec![0; 16].into(),
+            frames: 1,
+            wallclock_ms: 0,
+            timestamp: None,
+            sidecar: None,
+            entries: Vec::new(),
+            settings,
+        }
+    }
+
+    #[test]
+    fn put_object_request_has_the_caps_content_type() {
+        let request = S3MultiFrameSink::create_put_object_request(&frame_upload("image/png"));
+        assert_eq!(request.content_type.as_deref(), Some("image/png"));
+        let request = S3MultiFrameSink::create_put_object_request(&frame_upload("image/jpeg"));
+        assert_eq!(request.content_type.as_deref(), Some("image/jpeg"));
+        // Raw video has no image type of its own
+        let request = S3MultiFrameSink::create_put_object_request(&frame_upload("video/x-raw"));
+        assert_eq!(
+            request.content_type.as_deref(),
+            Some("application/octet-stream")
+        );
+    }
+
+    #[test]
+    fn default_keys_sort_in_frame_order() {
+        let settings = settings("captures", "/");
+        let template = KeyTemplate::from_str(&settings.default_key_template()).unwrap();
+        let keys: Vec<String> = (1..=150)
+            .map(|frame_num| {
+                let fields = KeyFields {
+                    prefix: settings.key_prefix(),
+                    frame_num,
+                    pts: None,
+                    timestamp: "0",
+                    meta: None,
+                    key_suffix: "",
+                };
+                template.render(&fields, ".png")
+            })
+            .collect();
+        assert_eq!(keys[0], "captures/frame0000000001.png");
+        let mut sorted = keys.clone();
+        sorted.sort();
+        assert_eq!(sorted, keys);
+    }
+
+    #[test]
+    fn content_md5_matches_a_known_vector() {
+        let mut frame = frame_upload("image/png");
+        frame.body = b"hello".to_vec().into();
+        assert_eq!(
+            S3MultiFrameSink::create_put_object_request(&frame).content_md5,
+            None
+        );
+        frame.settings.verify_integrity = true;
+        // MD5 of "hello" is 5d41402abc4b2a76b9719d911017c592
+        assert_eq!(
+            S3MultiFrameSink::create_put_object_request(&frame)
+                .content_md5
+                .as_deref(),
+            Some("XUFAKrxLKna5cZ2REBfFkg==")
+        );
+    }
+
+    #[test]
+    fn metadata_has_the_frame_number_and_pts() {
+        let mut frame = frame_upload("image/png");
+        frame.frame_num = 7;
+        frame.pts = Some(40_000_000);
+        let metadata = S3MultiFrameSink::create_put_object_request(&frame)
+            .metadata
+            .unwrap();
+        assert_eq!(metadata.len(), 2);
+        assert_eq!(metadata["frame-number"], "7");
+        assert_eq!(metadata["pts"], "40000000");
+
+        frame.settings.write_metadata = false;
+        assert!(S3MultiFrameSink::create_put_object_request(&frame)
+            .metadata
+            .is_none());
+    }
+
+    #[test]
+    fn key_prefix_drops_trailing_separators() {
+        assert_eq!(settings("captures", "/").key_prefix(), "captures");
+        assert_eq!(settings("captures/", "/").key_prefix(), "captures");
+        assert_eq!(settings("captures//", "/").key_prefix(), "captures");
+        assert_eq!(settings("a/b/", "/").key_prefix(), "a/b");
+        assert_eq!(settings("captures_", "_").key_prefix(), "captures");
+        assert_eq!(settings("captures/_", "_").key_prefix(), "captures");
+        assert_eq!(settings("captures/", "").key_prefix(), "captures");
+    }
+
+    #[test]
+    fn object_key_joins_with_a_single_separator() {
+        assert_eq!(
+            settings("captures/", "/").object_key("frame1.png"),
+            "captures/frame1.png"
+        );
+        assert_eq!(
+            settings("captures", "_").object_key("frame1.png"),
+            "captures_frame1.png"
+        );
+    }
+
+    #[test]
+    fn key_frame_num_finds_the_frame_after_folders() {
+        assert_eq!(key_frame_num("frame0000000042.png", "/"), Some(42));
+        assert_eq!(
+            key_frame_num("1920x1080/frame0000000007-1a2b3c4d.png", "/"),
+            Some(7)
+        );
+        assert_eq!(
+            key_frame_num("seg001_1920x1080_frame0000000003.png", "_"),
+            Some(3)
+        );
+        assert_eq!(key_frame_num("manifest.json", "/"), None);
+        assert_eq!(key_frame_num("batch0000000001.tar", "_"), None);
+    }
+
+    #[test]
+    fn clock_anchor_dates_frames_from_the_first() {
+        let anchor = ClockAnchor {
+            source: TimestampSource::Pts,
+            ns: 2_000_000_000,
+            wallclock_ms: 1_700_000_000_000,
+        };
+        assert_eq!(anchor.wallclock_ms(2_000_000_000), 1_700_000_000_000);
+        assert_eq!(anchor.wallclock_ms(3_500_000_000), 1_700_000_001_500);
+        assert_eq!(anchor.wallclock_ms(1_000_000_000), 1_699_999_999_000);
+    }
+
+    #[test]
+    fn gzip_round_trips() {
+        use flate2::read::GzDecoder;
+        use std::io::Read;
+
+        let frame: Vec<u8> = (0..10_000_u32).map(|i| (i % 7) as u8).collect();
+        let compressed = gzip(&frame);
+        assert!(compressed.len() < frame.len());
+        let mut decompressed = Vec::new();
+        GzDecoder::new(compressed.as_slice())
+            .read_to_end(&mut decompressed)
+            .unwrap();
+        assert_eq!(decompressed, frame);
+    }
+
+    #[test]
+    fn http_endpoint_is_used_without_tls() {
+        use std::io::Read;
+        use std::net::TcpListener;
+
+        // Answers one request and hands back what arrived on the socket
+        let listener = TcpListener::bind("127.0.0.1:0").unwrap();
+        let port = listener.local_addr().unwrap().port();
+        let server = std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = vec![0; 4096];
+            let len = stream.read(&mut request).unwrap();
+            stream
+                .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                .unwrap();
+            request.truncate(len);
+            request
+        });
+
+        let settings = Settings {
+            endpoint: Some(region::parse_endpoint(&format!("HTTP://127.0.0.1:{}", port)).unwrap()),
+            access_key: Some("access".to_string()),
+            secret_key: Some("secret".to_string()),
+            ..Default::default()
+        };
+        let s3client = S3MultiFrameSink::create_s3client(&settings).unwrap();
+        let mut runtime = runtime::Runtime::new().unwrap();
+        runtime
+            .block_on(s3client.head_bucket(HeadBucketRequest {
+                bucket: "bucket".to_string(),
+            }))
+            .unwrap();
+
+        // A TLS client would have opened with a handshake instead
+        let request = String::from_utf8(server.join().unwrap()).unwrap();
+        assert!(request.starts_with("HEAD /bucket HTTP/1.1\r\n"));
+    }
+
+    // An S3 stand-in on a local port, which answers every request with an empty
+    // 200 and records its method and path
+    struct MockS3 {
+        endpoint: String,
+        requests: Arc<Mutex<Vec<String>>>,
+    }
+
+    impl MockS3 {
+        fn start() -> Self {
+            let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+            let endpoint = format!("http://{}", listener.local_addr().unwrap());
+            let requests = Arc::new(Mutex::new(Vec::new()));
+            let recorded = requests.clone();
+            std::thread::spawn(move || {
+                for stream in listener.incoming() {
+                    let recorded = recorded.clone();
+                    std::thread::spawn(move || MockS3::serve(stream.unwrap(), &recorded));
+                }
+            });
+            MockS3 { endpoint, requests }
+        }
+
+        // Requests on a connection are kept alive, so each is read in turn
+        fn serve(mut stream: std::net::TcpStream, recorded: &Mutex<Vec<String>>) {
+            use std::io::{BufRead, BufReader, Read};
+
+            let mut reader = BufReader::new(stream.try_clone().unwrap());
+            loop {
+                let mut request_line = String::new();
+                if reader.read_line(&mut request_line).unwrap_or(0) == 0 {
+                    return;
+                }
+                let mut content_length = 0;
+                loop {
+                    let mut header = String::new();
+                    reader.read_line(&mut header).unwrap();
+                    let header = header.trim_end();
+                    if header.is_empty() {
+                        break;
+                    }
+                    if let Some((name, value)) = header.split_once(':') {
+                        if name.eq_ignore_ascii_case("content-length") {
+                            content_length = value.trim().parse().unwrap();
+                        }
+                    }
+                }
+                let mut body = vec![0; content_length];
+                reader.read_exact(&mut body).unwrap();
+                let mut parts = request_line.split(' ');
+                let method = parts.next().unwrap_or_default();
+                let path = parts.next().unwrap_or_default();
+                recorded
+                    .lock()
+                    .unwrap()
+                    .push(format!("{} {}", method, path));
+                stream
+                    .write_all(b"HTTP/1.1 200 OK\r\nContent-Length: 0\r\n\r\n")
+                    .unwrap();
+            }
+        }
+
+        // The paths of the frames written so far
+        fn frame_puts(&self) -> Vec<String> {
+            let mut puts: Vec<String> = self
+                .requests
+                .lock()
+                .unwrap()
+                .iter()
+                .filter_map(|request| request.strip_prefix("PUT "))
+                .filter(|path| path.contains("/frame"))
+                .map(|path| path.to_string())
+                .collect();
+            puts.sort();
+            puts
+        }
+    }
+
+    fn make_sink() -> gst::Element {
+        static REGISTER: std::sync::Once = std::sync::Once::new();
+        gst::init().unwrap();
+        REGISTER.call_once(|| {
+            gst::Element::register(
+                None,
+                "s3multiframesink",
+                gst::Rank::None,
+                S3MultiFrameSink::get_type(),
+            )
+            .unwrap();
+        });
+        gst::ElementFactory::make("s3multiframesink", None).unwrap()
+    }
+
+    // A playing sink writing to the mock, fed from a pad of the test's own
+    // rather than from a pipeline
+    fn start_sink(s3: &MockS3, properties: &[(&str, &dyn ToValue)]) -> (gst::Element, gst::Pad) {
+        let sink = make_sink();
+        sink.set_property("bucket", &"bucket").unwrap();
+        sink.set_property("key", &"captures").unwrap();
+        sink.set_property("endpoint", &s3.endpoint).unwrap();
+        sink.set_property("access-key", &"access").unwrap();
+        sink.set_property("secret-key", &"secret").unwrap();
+        sink.set_property("sync", &false).unwrap();
+        for &(name, value) in properties {
+            sink.set_property(name, value).unwrap();
+        }
+
+        let src = gst::Pad::new(Some("src"), gst::PadDirection::Src);
+        src.set_active(true).unwrap();
+        src.link(&sink.get_static_pad("sink").unwrap()).unwrap();
+        sink.set_state(gst::State::Playing).unwrap();
+        let caps = gst::Caps::new_simple(
+            "image/png",
+            &[
+                ("width", &16),
+                ("height", &16),
+                ("framerate", &gst::Fraction::new(0, 1)),
+            ],
+        );
+        assert!(src.push_event(gst::Event::new_stream_start("test").build()));
+        assert!(src.push_event(gst::Event::new_caps(&caps).build()));
+        let segment = gst::FormattedSegment::<gst::ClockTime>::new();
+        assert!(src.push_event(gst::Event::new_segment(&segment).build()));
+        (sink, src)
+    }
+
+    #[test]
+    fn start_without_a_bucket_fails_cleanly() {
+        let sink = make_sink();
+        sink.set_property("key", &"captures").unwrap();
+        let bus = gst::Bus::new();
+        sink.set_bus(Some(&bus));
+        assert!(sink.set_state(gst::State::Paused).is_err());
+
+        let message = bus.pop_filtered(&[gst::MessageType::Error]).unwrap();
+        match message.view() {
+            gst::MessageView::Error(error) => {
+                assert!(error.get_error().is::<gst::ResourceError>());
+                assert!(error.get_debug().unwrap().contains("No bucket specified"));
+            }
+            _ => unreachable!(),
+        }
+        sink.set_state(gst::State::Null).unwrap();
+    }
+
+    #[test]
+    fn eos_waits_for_async_uploads() {
+        let s3 = MockS3::start();
+        let (sink, src) = start_sink(&s3, &[("async-uploads", &true), ("max-in-flight", &4_u32)]);
+        for _ in 0..8 {
+            src.push(gst::Buffer::from_slice(vec![1; 1024])).unwrap();
+        }
+        assert!(src.push_event(gst::Event::new_eos().build()));
+
+        let expected: Vec<String> = (1..=8)
+            .map(|frame| format!("/bucket/captures/frame{:010}.png", frame))
+            .collect();
+        assert_eq!(s3.frame_puts(), expected);
+        sink.set_state(gst::State::Null).unwrap();
+    }
+
+    #[test]
+    fn empty_buffers_are_skipped_unnumbered() {
+        let s3 = MockS3::start();
+        let (sink, src) = start_sink(&s3, &[]);
+        src.push(gst::Buffer::new()).unwrap();
+        src.push(gst::Buffer::from_slice(vec![1; 1024])).unwrap();
+        assert!(src.push_event(gst::Event::new_eos().build()));
+
+        assert_eq!(
+            s3.frame_puts(),
+            vec!["/bucket/captures/frame0000000001.png".to_string()]
+        );
+        let uploaded = sink.get_property("frames-uploaded").unwrap();
+        assert_eq!(uploaded.get_some::<u64>().unwrap(), 1);
+        sink.set_state(gst::State::Null).unwrap();
+    }
 }
@@ -0,0 +1,61 @@
+const BLOCK_SIZE: usize = 512;
+const NAME_LEN: usize = 100;
+
+// An in-memory ustar archive, used to batch several frames into one object
+#[derive(Debug, Default)]
+pub struct TarArchive {
+    buffer: Vec<u8>,
+    entries: u64,
+}
+
+impl TarArchive {
+    pub fn entries(&self) -> u64 {
+        self.entries
+    }
+
+    pub fn append(&mut self, name: &str, data: &[u8], mtime: u64) -> Result<(), String> {
+        if name.is_empty() || name.len() > NAME_LEN {
+            return Err(format!(
+                "entry name {} must be between 1 and {} bytes",
+                name, NAME_LEN
+            ));
+        }
+
+        let mut header = [0_u8; BLOCK_SIZE];
+        header[..name.len()].copy_from_slice(name.as_bytes());
+        write_octal(&mut header[100..108], 0o644);
+        write_octal(&mut header[108..116], 0);
+        write_octal(&mut header[116..124], 0);
+        write_octal(&mut header[124..136], data.len() as u64);
+        write_octal(&mut header[136..148], mtime);
+        header[156] = b'0';
+        header[257..263].copy_from_slice(b"ustar\0");
+        header[263..265].copy_from_slice(b"00");
+        // The checksum is computed with its own field filled with spaces
+        header[148..156].copy_from_slice(b"        ");
+        let checksum: u64 = header.iter().map(|&byte| u64::from(byte)).sum();
+        write_octal(&mut header[148..155], checksum);
+        header[155] = b' ';
+
+        self.buffer.extend_from_slice(&header);
+        self.buffer.extend_from_slice(data);
+        let padding = (BLOCK_SIZE - data.len() % BLOCK_SIZE) % BLOCK_SIZE;
+        self.buffer.resize(self.buffer.len() + padding, 0);
+        self.entries += 1;
+        Ok(())
+    }
+
+    // Terminates the archive with the two empty blocks tar expects
+    pub fn finish(mut self) -> Vec<u8> {
+        self.buffer.resize(self.buffer.len() + 2 * BLOCK_SIZE, 0);
+        self.buffer
+    }
+}
+
+// Writes a zero-padded, NUL-terminated octal number filling the field
+fn write_octal(field: &mut [u8], value: u64) {
+    let digits = format!("{:0width$o}", value, width = field.len() - 1);
+    let digits = &digits.as_bytes()[digits.len() - (field.len() - 1)..];
+    field[..digits.len()].copy_from_slice(digits);
+    field[digits.len()] = 0;
+}
@@ -0,0 +1,118 @@
+use crate::date_partition::{self, MONTHS, WEEKDAYS};
+use std::fmt::Write;
+use std::str::FromStr;
+
+#[derive(Debug, Clone, PartialEq)]
+enum Item {
+    Literal(String),
+    Year,
+    Month,
+    Day,
+    Hour,
+    Minute,
+    Second,
+    Millis,
+    UnixSeconds,
+    WeekdayName,
+    MonthName,
+}
+
+// A strftime-style pattern such as `%Y-%m-%dT%H:%M:%S.%3fZ`, rendered in UTC.
+// Only the specifiers below are supported, so that a typo is caught when the
+// pattern is set rather than showing up in every key.
+#[derive(Debug, Clone, PartialEq)]
+pub struct TimestampFormat {
+    pattern: String,
+    items: Vec<Item>,
+}
+
+impl TimestampFormat {
+    pub fn pattern(&self) -> &str {
+        &self.pattern
+    }
+
+    pub fn render(&self, timestamp_ms: u128) -> String {
+        let (year, month, day, hour, minute) = date_partition::utc_fields(timestamp_ms);
+        let mut rendered = String::new();
+        for item in &self.items {
+            match *item {
+                Item::Literal(ref literal) => rendered.push_str(literal),
+                Item::Year => write!(rendered, "{:04}", year).unwrap(),
+                Item::Month => write!(rendered, "{:02}", month).unwrap(),
+                Item::Day => write!(rendered, "{:02}", day).unwrap(),
+                Item::Hour => write!(rendered, "{:02}", hour).unwrap(),
+                Item::Minute => write!(rendered, "{:02}", minute).unwrap(),
+                Item::Second => write!(rendered, "{:02}", timestamp_ms / 1000 % 60).unwrap(),
+                Item::Millis => write!(rendered, "{:03}", timestamp_ms % 1000).unwrap(),
+                Item::UnixSeconds => write!(rendered, "{}", timestamp_ms / 1000).unwrap(),
+                Item::WeekdayName => {
+                    rendered.push_str(WEEKDAYS[date_partition::weekday(timestamp_ms)])
+                }
+                Item::MonthName => rendered.push_str(MONTHS[month as usize - 1]),
+            }
+        }
+        rendered
+    }
+}
+
+impl FromStr for TimestampFormat {
+    type Err = String;
+
+    fn from_str(pattern: &str) -> Result<Self, Self::Err> {
+        let mut items = Vec::new();
+        let mut literal = String::new();
+        let mut chars = pattern.chars();
+        while let Some(c) = chars.next() {
+            if c != '%' {
+                literal.push(c);
+                continue;
+            }
+            let specifier = match chars.next() {
+                Some('%') => {
+                    literal.push('%');
+                    continue;
+                }
+                Some('3') => match chars.next() {
+                    Some('f') => vec![Item::Millis],
+                    _ => return Err(format!("expected %3f in {}", pattern)),
+                },
+                Some('Y') => vec![Item::Year],
+                Some('m') => vec![Item::Month],
+                Some('d') => vec![Item::Day],
+                Some('H') => vec![Item::Hour],
+                Some('M') => vec![Item::Minute],
+                Some('S') => vec![Item::Second],
+                Some('s') => vec![Item::UnixSeconds],
+                Some('a') => vec![Item::WeekdayName],
+                Some('b') => vec![Item::MonthName],
+                Some('F') => vec![
+                    Item::Year,
+                    Item::Literal("-".to_string()),
+                    Item::Month,
+                    Item::Literal("-".to_string()),
+                    Item::Day,
+                ],
+                Some('T') => vec![
+                    Item::Hour,
+                    Item::Literal(":".to_string()),
+                    Item::Minute,
+                    Item::Literal(":".to_string()),
+                    Item::Second,
+                ],
+                Some(c) => return Err(format!("unsupported specifier %{} in {}", c, pattern)),
+                None => return Err(format!("trailing % in {}", pattern)),
+            };
+            if !literal.is_empty() {
+                items.push(Item::Literal(literal.split_off(0)));
+            }
+            items.extend(specifier);
+        }
+        if !literal.is_empty() {
+            items.push(Item::Literal(literal));
+        }
+        Ok(TimestampFormat {
+            pattern: pattern.to_string(),
+            items,
+        })
+    }
+}
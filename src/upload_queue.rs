@@ -0,0 +1,82 @@
+use std::future::Future;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::mpsc;
+use std::sync::Arc;
+use tokio::runtime;
+use tokio::sync::{Notify, OwnedSemaphorePermit, Semaphore};
+
+// A place in the queue, reserved before spawning an upload
+pub struct Slot(OwnedSemaphorePermit);
+
+// Runs uploads in the background on the runtime, with at most `max_in_flight`
+// of them pending at once. Results are collected through a channel.
+pub struct UploadQueue<T> {
+    handle: runtime::Handle,
+    in_flight: Arc<Semaphore>,
+    // Counted separately from the permits, so that draining doesn't have to
+    // acquire all max_in_flight of them
+    pending: Arc<AtomicUsize>,
+    idle: Arc<Notify>,
+    sender: mpsc::Sender<T>,
+    receiver: mpsc::Receiver<T>,
+}
+
+impl<T: Send + 'static> UploadQueue<T> {
+    pub fn new(handle: runtime::Handle, max_in_flight: usize) -> Self {
+        let (sender, receiver) = mpsc::channel();
+        UploadQueue {
+            handle,
+            in_flight: Arc::new(Semaphore::new(max_in_flight)),
+            pending: Arc::new(AtomicUsize::new(0)),
+            idle: Arc::new(Notify::new()),
+            sender,
+            receiver,
+        }
+    }
+
+    // Resolves once a slot frees up, when the queue is full. Giving up on the
+    // wait leaves the queue as it was.
+    pub fn slot(&self) -> impl Future<Output = Slot> {
+        let in_flight = self.in_flight.clone();
+        async move { Slot(in_flight.acquire_owned().await) }
+    }
+
+    pub fn spawn<F>(&self, slot: Slot, upload: F)
+    where
+        F: Future<Output = T> + Send + 'static,
+    {
+        let Slot(permit) = slot;
+        let sender = self.sender.clone();
+        let pending = self.pending.clone();
+        let idle = self.idle.clone();
+        pending.fetch_add(1, Ordering::SeqCst);
+        self.handle.spawn(async move {
+            let result = upload.await;
+            // The receiver lives as long as the queue, which outlives its permits
+            let _ = sender.send(result);
+            drop(permit);
+            if pending.fetch_sub(1, Ordering::SeqCst) == 1 {
+                idle.notify();
+            }
+        });
+    }
+
+    // Whether spawn would block
+    pub fn is_full(&self) -> bool {
+        self.in_flight.available_permits() == 0
+    }
+
+    pub fn completed(&self) -> Vec<T> {
+        self.receiver.try_iter().collect()
+    }
+
+    // Waits for every pending upload to finish
+    pub async fn drain(&self) -> Vec<T> {
+        // A notification from an earlier drain may be left over, so the count
+        // is checked again after each one
+        while self.pending.load(Ordering::SeqCst) > 0 {
+            self.idle.notified().await;
+        }
+        self.completed()
+    }
+}